@@ -10,12 +10,14 @@ import Jwt.Http
 
 
 type alias AuthorizedResponse =
-    { token : String }
+    { token : String, refreshToken : String }
 
 
 authorizedResponseDecoder : D.Decoder AuthorizedResponse
 authorizedResponseDecoder =
-    D.map AuthorizedResponse (D.field "token" D.string)
+    D.map2 AuthorizedResponse
+        (D.field "token" D.string)
+        (D.field "refreshToken" D.string)
 
 
 login :
@@ -61,3 +63,16 @@ register { username, password, email } toMsg =
         , expect = Api.Http.expectJson toMsg authorizedResponseDecoder
         }
         |> Effect.sendCmd
+
+
+{-| Swaps the refresh token for a new token and refresh token. Each refresh
+token only works once.
+-}
+refresh : String -> (Result Api.Http.Error AuthorizedResponse -> msg) -> Effect msg
+refresh refreshToken toMsg =
+    Http.post
+        { url = "/api/auth/refresh"
+        , body = Http.jsonBody (E.object [ ( "refreshToken", E.string refreshToken ) ])
+        , expect = Api.Http.expectJson toMsg authorizedResponseDecoder
+        }
+        |> Effect.sendCmd
@@ -16,17 +16,19 @@ type alias Puzzle =
     , sig : String
     , timestamp : Int
     , attribution : String
+    , seed : Int
     }
 
 
 puzzleDecoder : D.Decoder Puzzle
 puzzleDecoder =
-    D.map5 Puzzle
+    D.map6 Puzzle
         (D.field "id" D.int)
         (D.field "ciphertext" (D.array D.string))
         (D.field "sig" D.string)
         (D.field "timestamp" D.int)
         (D.field "attribution" D.string)
+        (D.field "seed" D.int)
 
 
 new : Maybe String -> (Result Api.Http.Error Puzzle -> msg) -> Effect msg
@@ -54,10 +56,11 @@ submit :
         , message : String
         , sig : String
         , timestamp : Int
+        , seed : Int
         }
     -> (Result Api.Http.Error Api.Puzzle.SubmitResponse -> msg)
     -> Effect msg
-submit maybeToken { id, message, sig, timestamp } toMsg =
+submit maybeToken { id, message, sig, timestamp, seed } toMsg =
     case maybeToken of
         Just token ->
             Jwt.Http.post token
@@ -69,6 +72,7 @@ submit maybeToken { id, message, sig, timestamp } toMsg =
                             , ( "message", E.string message )
                             , ( "sig", E.string sig )
                             , ( "timestamp", E.int timestamp )
+                            , ( "seed", E.int seed )
                             ]
                         )
                 , expect = Api.Http.expectJson toMsg Api.Puzzle.submitResponseDecoder
@@ -85,6 +89,7 @@ submit maybeToken { id, message, sig, timestamp } toMsg =
                             , ( "message", E.string message )
                             , ( "sig", E.string sig )
                             , ( "timestamp", E.int timestamp )
+                            , ( "seed", E.int seed )
                             ]
                         )
                 , expect = Api.Http.expectJson toMsg Api.Puzzle.submitResponseDecoder
@@ -1,16 +1,37 @@
 module Api.Http exposing (..)
 
+import Dict exposing (Dict)
 import Http
 import Json.Decode as D
 
 
+{-| `errors` has what is wrong with each field of the request, if it was
+invalid.
+-}
 type alias ResponseStatusError =
-    { status : Int, message : String }
+    { status : Int, message : String, errors : Dict String (List String) }
 
 
 responseStatusErrorDecoder : D.Decoder ResponseStatusError
 responseStatusErrorDecoder =
-    D.map2 ResponseStatusError (D.field "status" D.int) (D.field "message" D.string)
+    D.map3 ResponseStatusError
+        (D.field "status" D.int)
+        (D.field "message" D.string)
+        (D.oneOf [ D.field "errors" (D.dict (D.list D.string)), D.succeed Dict.empty ])
+
+
+{-| The problem with each field, like "username has been taken", or the message
+if the error isn't about any one field.
+-}
+describe : ResponseStatusError -> String
+describe { message, errors } =
+    if Dict.isEmpty errors then
+        message
+
+    else
+        Dict.toList errors
+            |> List.concatMap (\( field, problems ) -> List.map (\problem -> field ++ " " ++ problem) problems)
+            |> String.join ", "
 
 
 type Error
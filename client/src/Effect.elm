@@ -146,9 +146,9 @@ confetti =
     Confetti
 
 -- AUTH
-login : String -> Effect msg
-login token =
-    SendSharedMsg (Shared.Msg.Login token)
+login : Shared.Msg.Tokens -> Effect msg
+login tokens =
+    SendSharedMsg (Shared.Msg.Login tokens)
 
 -- INTERNALS
 
@@ -226,6 +226,7 @@ updateSuccess shared msg model puzzle =
                                 |> String.fromList
                         , sig = puzzle.sig
                         , timestamp = puzzle.timestamp
+                        , seed = puzzle.seed
                         }
                         GotSubmitResponse
                     )
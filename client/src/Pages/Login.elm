@@ -79,9 +79,9 @@ update msg model =
             , Api.Auth.login { username = model.username, password = model.password } GotResponse
             )
 
-        GotResponse (Ok { token }) ->
+        GotResponse (Ok tokens) ->
             ( { model | isSubmitting = False }
-            , Effect.login token
+            , Effect.login tokens
             )
 
         GotResponse (Err (Api.Http.BadStatus { message })) ->
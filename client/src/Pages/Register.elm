@@ -90,13 +90,13 @@ update msg model =
                 GotResponse
             )
 
-        GotResponse (Ok { token }) ->
+        GotResponse (Ok tokens) ->
             ( { model | isSubmitting = False }
-            , Effect.login token
+            , Effect.login tokens
             )
 
-        GotResponse (Err (Api.Http.BadStatus { message })) ->
-            ( { model | isSubmitting = False, badRegister = message }, Effect.none )
+        GotResponse (Err (Api.Http.BadStatus err)) ->
+            ( { model | isSubmitting = False, badRegister = Api.Http.describe err }, Effect.none )
 
         GotResponse (Err _) ->
             ( { model | isSubmitting = False, badRegister = "Something went wrong..." }, Effect.none )
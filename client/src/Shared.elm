@@ -1,4 +1,4 @@
-module Shared exposing
+port module Shared exposing
     ( Flags, decoder
     , Model, Msg
     , init, update, subscriptions
@@ -13,10 +13,12 @@ module Shared exposing
 -}
 
 import Api.Auth
+import Api.Http
 import Dict
 import Effect exposing (Effect)
 import Json.Decode as D
 import Json.Encode as E
+import Jwt
 import Route exposing (Route)
 import Route.Path
 import Settings
@@ -24,6 +26,7 @@ import Settings.Theme
 import Shared.Model
 import Shared.Msg exposing (Msg(..))
 import Api.Profile
+import Time
 
 
 
@@ -33,14 +36,16 @@ import Api.Profile
 type alias Flags =
     { settings : Maybe Settings.Settings
     , token : Maybe String
+    , refreshToken : Maybe String
     }
 
 
 decoder : D.Decoder Flags
 decoder =
-    D.map2 Flags
+    D.map3 Flags
         (D.maybe (D.field "settings" Settings.settingsDecoder))
         (D.maybe (D.field "token" D.string))
+        (D.maybe (D.field "refreshToken" D.string))
 
 
 
@@ -57,7 +62,7 @@ init flagsResult route =
         flags : Flags
         flags =
             flagsResult
-                |> Result.withDefault { settings = Nothing, token = Nothing }
+                |> Result.withDefault { settings = Nothing, token = Nothing, refreshToken = Nothing }
 
         defaultSettings =
             { theme = Settings.Theme.Auto }
@@ -65,15 +70,59 @@ init flagsResult route =
         settings =
             Maybe.withDefault defaultSettings flags.settings
     in
-    ( { settings = settings, token = flags.token, user = Nothing }
+    ( { settings = settings, token = flags.token, refreshToken = flags.refreshToken, user = Nothing }
     , Effect.batch
         [ Effect.sendCmd (Settings.Theme.updateTheme (Settings.Theme.encoder settings.theme))
-        , Maybe.withDefault Effect.none (Maybe.map2 Api.Profile.myProfile flags.token (Just GotProfile))
+        , case ( flags.refreshToken, flags.token ) of
+            -- The token has likely expired since the last visit
+            ( Just refreshToken, _ ) ->
+                Api.Auth.refresh refreshToken GotRefresh
+
+            ( Nothing, Just token ) ->
+                Api.Profile.myProfile token GotProfile
+
+            ( Nothing, Nothing ) ->
+                Effect.none
         ]
     )
 
 
 
+-- TOKENS
+
+
+{-| Sent by interop.js when another tab changes the tokens
+-}
+port tokensChanged : ({ token : Maybe String, refreshToken : Maybe String } -> msg) -> Sub msg
+
+
+{-| Tokens are refreshed once they are this close to expiring. They last 15
+minutes.
+-}
+refreshMargin : Int
+refreshMargin =
+    5 * 60 * 1000
+
+
+expiresSoon : Time.Posix -> Maybe String -> Bool
+expiresSoon now token =
+    case Maybe.map (Jwt.decodeToken (D.field "exp" D.int)) token of
+        Just (Ok exp) ->
+            exp * 1000 - Time.posixToMillis now < refreshMargin
+
+        _ ->
+            True
+
+
+saveTokens : Shared.Msg.Tokens -> Effect Msg
+saveTokens tokens =
+    Effect.batch
+        [ Effect.save "token" (E.string tokens.token)
+        , Effect.save "refreshToken" (E.string tokens.refreshToken)
+        ]
+
+
+
 -- UPDATE
 
 
@@ -106,16 +155,16 @@ update route msg model =
                 ]
             )
 
-        Login token ->
-            ( { model | token = Just token }
+        Login tokens ->
+            ( { model | token = Just tokens.token, refreshToken = Just tokens.refreshToken }
             , Effect.batch
                 [ Effect.pushRoute
                     { path = Route.Path.Home_
                     , query = Dict.empty
                     , hash = Nothing
                     }
-                , Effect.save "token" (E.string token)
-                , Api.Profile.myProfile token GotProfile
+                , saveTokens tokens
+                , Api.Profile.myProfile tokens.token GotProfile
                 ]
             )
 
@@ -125,6 +174,58 @@ update route msg model =
         GotProfile (Err _) ->
             ( { model | token = Nothing }, Effect.none )
 
+        Tick now ->
+            case model.refreshToken of
+                Just refreshToken ->
+                    if expiresSoon now model.token then
+                        ( model, Api.Auth.refresh refreshToken GotRefresh )
+
+                    else
+                        ( model, Effect.none )
+
+                Nothing ->
+                    ( model, Effect.none )
+
+        GotRefresh (Ok tokens) ->
+            ( { model | token = Just tokens.token, refreshToken = Just tokens.refreshToken }
+            , Effect.batch
+                [ saveTokens tokens
+                , case model.user of
+                    Just _ ->
+                        Effect.none
+
+                    Nothing ->
+                        Api.Profile.myProfile tokens.token GotProfile
+                ]
+            )
+
+        GotRefresh (Err (Api.Http.BadStatus { status })) ->
+            if status == 401 then
+                -- The session is over, log in again
+                ( { model | token = Nothing, refreshToken = Nothing, user = Nothing }
+                , Effect.batch
+                    [ Effect.save "token" E.null
+                    , Effect.save "refreshToken" E.null
+                    ]
+                )
+
+            else
+                ( model, Effect.none )
+
+        -- Tried again on the next tick
+        GotRefresh (Err _) ->
+            ( model, Effect.none )
+
+        TokensChanged tokens ->
+            ( { model | token = tokens.token, refreshToken = tokens.refreshToken }
+            , case ( model.user, tokens.token ) of
+                ( Nothing, Just token ) ->
+                    Api.Profile.myProfile token GotProfile
+
+                _ ->
+                    Effect.none
+            )
+
 
 
 -- SUBSCRIPTIONS
@@ -132,4 +233,12 @@ update route msg model =
 
 subscriptions : Route () -> Model -> Sub Msg
 subscriptions route model =
-    Sub.none
+    Sub.batch
+        [ case model.refreshToken of
+            Just _ ->
+                Time.every (60 * 1000) Tick
+
+            Nothing ->
+                Sub.none
+        , tokensChanged TokensChanged
+        ]
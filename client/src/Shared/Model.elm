@@ -6,5 +6,6 @@ import Auth.User
 type alias Model =
     { settings : Settings.Settings
     , token : Maybe String
+    , refreshToken : Maybe String
     , user : Maybe Auth.User.User
     }
@@ -1,11 +1,19 @@
-module Shared.Msg exposing (Msg(..))
+module Shared.Msg exposing (Msg(..), Tokens)
 
 import Api.Http
 import Auth.User
 import Settings
+import Time
+
+
+type alias Tokens =
+    { token : String, refreshToken : String }
 
 
 type Msg
     = ChangeSetting Settings.Setting
-    | Login String
+    | Login Tokens
     | GotProfile (Result Api.Http.Error Auth.User.User)
+    | Tick Time.Posix
+    | GotRefresh (Result Api.Http.Error Tokens)
+    | TokensChanged { token : Maybe String, refreshToken : Maybe String }
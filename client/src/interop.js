@@ -4,6 +4,7 @@ export const flags = ({ env }) => {
   return {
     settings: JSON.parse(localStorage.settings || null),
     token: JSON.parse(localStorage.token || null),
+    refreshToken: JSON.parse(localStorage.refreshToken || null),
   };
 };
 
@@ -30,6 +31,19 @@ export const onReady = ({ app, env }) => {
       });
     }
 
+    if (app.ports.tokensChanged) {
+      // Refresh tokens only work once, so when another tab refreshes or logs
+      // in this one has to switch to the new tokens too
+      window.addEventListener("storage", (event) => {
+        if (event.key === "token" || event.key === "refreshToken") {
+          app.ports.tokensChanged.send({
+            token: JSON.parse(localStorage.token || null),
+            refreshToken: JSON.parse(localStorage.refreshToken || null),
+          });
+        }
+      });
+    }
+
     if (app.ports.launchConfetti) {
       app.ports.launchConfetti.subscribe(() => {
         fire(0.25, {
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS achievements(
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(63) NOT NULL UNIQUE,
+    description VARCHAR(255) NOT NULL,
+    kind SMALLINT NOT NULL,
+    puzzle_type SMALLINT,
+    threshold INT NOT NULL,
+    exp_reward INT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS user_achievements(
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id),
+    achievement_id INT NOT NULL REFERENCES achievements(id),
+    unlocked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, achievement_id)
+);
+
+-- kind: 0 = solve count, 1 = solved under threshold ms, 2 = streak of threshold days
+-- puzzle_type: 0 = Aristocrat, 1 = Baconian, NULL = any
+INSERT INTO achievements(name, description, kind, puzzle_type, threshold, exp_reward) VALUES
+    ('First Aristocrat', 'Solve your first Aristocrat', 0, 0, 1, 50),
+    ('First Baconian', 'Solve your first Baconian', 0, 1, 1, 50),
+    ('Speed Demon', 'Solve an Aristocrat in under 60 seconds', 1, 0, 60000, 200),
+    ('Dedicated', 'Solve a puzzle 7 days in a row', 2, NULL, 7, 300),
+    ('Bacon Connoisseur', 'Solve 100 Baconians', 0, 1, 100, 500);
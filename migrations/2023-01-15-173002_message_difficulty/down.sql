@@ -0,0 +1,2 @@
+DROP INDEX messages_difficulty_idx;
+ALTER TABLE messages DROP COLUMN difficulty;
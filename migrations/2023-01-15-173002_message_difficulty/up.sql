@@ -0,0 +1,4 @@
+-- Filled in by `cryptopuz backfill-difficulty`
+ALTER TABLE messages ADD COLUMN difficulty REAL;
+
+CREATE INDEX messages_difficulty_idx ON messages(difficulty);
@@ -0,0 +1,2 @@
+DROP TABLE message_ratings;
+DROP TABLE ratings;
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS ratings(
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id),
+    puzzle_type SMALLINT NOT NULL,
+    rating REAL NOT NULL,
+    solves INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, puzzle_type)
+);
+
+CREATE TABLE IF NOT EXISTS message_ratings(
+    message_id INT NOT NULL REFERENCES messages(id),
+    puzzle_type SMALLINT NOT NULL,
+    rating REAL NOT NULL,
+    solves INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (message_id, puzzle_type)
+);
+
+CREATE INDEX message_ratings_rating_idx ON message_ratings(puzzle_type, rating);
@@ -0,0 +1 @@
+DROP TABLE puzzle_attempts;
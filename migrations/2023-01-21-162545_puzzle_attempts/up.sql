@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS puzzle_attempts(
+    id SERIAL PRIMARY KEY,
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id),
+    puzzle_type SMALLINT NOT NULL,
+    message_id INT NOT NULL REFERENCES messages(id),
+    -- the timestamp the puzzle was signed with, in milliseconds
+    started_at BIGINT NOT NULL,
+    wrong_submissions INT NOT NULL DEFAULT 0,
+    solved_at TIMESTAMPTZ,
+    UNIQUE (user_id, puzzle_type, message_id, started_at)
+);
@@ -0,0 +1,2 @@
+DROP TABLE test_questions;
+DROP TABLE tests;
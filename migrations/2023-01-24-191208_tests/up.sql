@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS tests(
+    id VARCHAR(24) PRIMARY KEY,
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- in seconds
+    time_limit INT NOT NULL,
+    submitted_at TIMESTAMPTZ,
+    score INT
+);
+
+CREATE TABLE IF NOT EXISTS test_questions(
+    test_id VARCHAR(24) NOT NULL REFERENCES tests(id),
+    position SMALLINT NOT NULL,
+    puzzle_type SMALLINT NOT NULL,
+    message_id INT NOT NULL REFERENCES messages(id),
+    ciphertext TEXT NOT NULL,
+    points INT NOT NULL,
+    timed BOOLEAN NOT NULL DEFAULT FALSE,
+    answer TEXT,
+    answered_at TIMESTAMPTZ,
+    errors INT,
+    score INT,
+    PRIMARY KEY (test_id, position)
+);
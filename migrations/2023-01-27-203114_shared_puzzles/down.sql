@@ -0,0 +1,2 @@
+DROP TABLE shared_solves;
+DROP TABLE shared_puzzles;
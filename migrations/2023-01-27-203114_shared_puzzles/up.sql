@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS shared_puzzles(
+    code VARCHAR(12) PRIMARY KEY,
+    puzzle_type SMALLINT NOT NULL,
+    message_id INT NOT NULL REFERENCES messages(id),
+    -- the seed the alphabet or variant was generated from
+    seed BIGINT NOT NULL,
+    created_by VARCHAR(24) REFERENCES users(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (puzzle_type, message_id, seed)
+);
+
+CREATE TABLE IF NOT EXISTS shared_solves(
+    code VARCHAR(12) NOT NULL REFERENCES shared_puzzles(code),
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id),
+    solved_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    time_taken INT NOT NULL,
+    errors INT NOT NULL,
+    score INT NOT NULL,
+    PRIMARY KEY (code, user_id)
+);
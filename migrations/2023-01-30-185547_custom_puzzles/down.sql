@@ -0,0 +1,2 @@
+DROP TABLE custom_solves;
+DROP TABLE custom_puzzles;
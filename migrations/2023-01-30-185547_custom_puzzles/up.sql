@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS custom_puzzles(
+    code VARCHAR(12) PRIMARY KEY,
+    author VARCHAR(24) NOT NULL REFERENCES users(id),
+    puzzle_type SMALLINT NOT NULL,
+    plaintext VARCHAR(255) NOT NULL,
+    attribution VARCHAR(127),
+    visibility SMALLINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX custom_puzzles_author ON custom_puzzles(author);
+
+CREATE TABLE IF NOT EXISTS custom_solves(
+    code VARCHAR(12) NOT NULL REFERENCES custom_puzzles(code) ON DELETE CASCADE,
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id),
+    solved_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    time_taken INT NOT NULL,
+    errors INT NOT NULL,
+    score INT NOT NULL,
+    PRIMARY KEY (code, user_id)
+);
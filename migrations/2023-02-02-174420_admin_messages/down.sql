@@ -0,0 +1,5 @@
+DROP INDEX messages_message_key;
+ALTER TABLE messages ADD CONSTRAINT messages_message_key UNIQUE (message);
+ALTER TABLE messages DROP COLUMN deleted_at;
+
+ALTER TABLE users DROP COLUMN role;
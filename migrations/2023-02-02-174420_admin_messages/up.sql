@@ -0,0 +1,7 @@
+ALTER TABLE users ADD COLUMN role SMALLINT NOT NULL DEFAULT 0;
+
+-- deleted messages stay around for the solves that reference them, so only
+-- live messages need to be unique
+ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE messages DROP CONSTRAINT messages_message_key;
+CREATE UNIQUE INDEX messages_message_key ON messages(message) WHERE deleted_at IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE messages ADD COLUMN language VARCHAR(16) NOT NULL DEFAULT 'en';
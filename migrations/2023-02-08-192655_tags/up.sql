@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS tags(
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(32) NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS message_tags(
+    tag_id INT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    message_id INT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    PRIMARY KEY (tag_id, message_id)
+);
+
+CREATE INDEX message_tags_message_id ON message_tags(message_id);
+
+INSERT INTO tags(name) VALUES
+    ('science'),
+    ('history'),
+    ('literature'),
+    ('sports');
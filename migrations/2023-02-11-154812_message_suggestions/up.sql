@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS message_suggestions(
+    id SERIAL PRIMARY KEY,
+    submitted_by VARCHAR(24) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message VARCHAR(255) NOT NULL,
+    attribution VARCHAR(127),
+    status SMALLINT NOT NULL DEFAULT 0,
+    submitted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    reviewed_by VARCHAR(24) REFERENCES users(id) ON DELETE SET NULL,
+    reviewed_at TIMESTAMPTZ,
+    reason VARCHAR(255),
+    message_id INT REFERENCES messages(id),
+    -- The exp the submitter was given for it, once it is in use
+    credited_exp INTEGER
+);
+
+CREATE INDEX message_suggestions_submitted_by ON message_suggestions(submitted_by);
+CREATE INDEX message_suggestions_status ON message_suggestions(status);
+CREATE INDEX message_suggestions_message_id ON message_suggestions(message_id);
@@ -0,0 +1,2 @@
+UPDATE users SET role = 0 WHERE role = 1;
+UPDATE users SET role = 1 WHERE role = 2;
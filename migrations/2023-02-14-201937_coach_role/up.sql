@@ -0,0 +1,2 @@
+-- Coaches rank between students and admins
+UPDATE users SET role = 2 WHERE role = 1;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS sessions(
+    id VARCHAR(24) PRIMARY KEY,
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- SHA-256 of the current refresh token, and of the one it replaced so
+    -- reuse of a stolen token can be noticed
+    refresh_token_hash VARCHAR(64) NOT NULL UNIQUE,
+    previous_token_hash VARCHAR(64),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX sessions_user_id ON sessions(user_id);
+CREATE INDEX sessions_previous_token_hash ON sessions(previous_token_hash);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS password_resets(
+    -- SHA-256 of the token sent in the reset link
+    token_hash VARCHAR(64) PRIMARY KEY,
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX password_resets_user_id ON password_resets(user_id);
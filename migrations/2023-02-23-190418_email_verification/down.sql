@@ -0,0 +1,3 @@
+DROP TABLE email_verifications;
+DROP INDEX users_email_key;
+ALTER TABLE users DROP COLUMN email_verified_at;
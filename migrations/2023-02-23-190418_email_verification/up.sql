@@ -0,0 +1,34 @@
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMPTZ;
+
+-- emails are compared lowercased. Accounts that would end up sharing an email
+-- have to be sorted out by hand before this can run
+UPDATE users SET email = NULLIF(LOWER(TRIM(email)), '');
+DO $$
+DECLARE
+    conflicts TEXT;
+BEGIN
+    SELECT string_agg(email || ' (' || usernames || ')', '; ') INTO conflicts FROM (
+        SELECT email, string_agg(username, ', ' ORDER BY created_at) AS usernames
+        FROM users
+        WHERE email IS NOT NULL
+        GROUP BY email
+        HAVING COUNT(*) > 1
+    ) AS duplicates;
+    IF conflicts IS NOT NULL THEN
+        RAISE EXCEPTION 'emails used by more than one account: %', conflicts
+            USING HINT = 'change or clear the email of all but one account of each';
+    END IF;
+END $$;
+CREATE UNIQUE INDEX users_email_key ON users(email);
+
+CREATE TABLE IF NOT EXISTS email_verifications(
+    -- SHA-256 of the token sent in the verification link
+    token_hash VARCHAR(64) PRIMARY KEY,
+    user_id VARCHAR(24) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- the address the link was sent to, in case the user's email has changed
+    email VARCHAR(127) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX email_verifications_user_id ON email_verifications(user_id);
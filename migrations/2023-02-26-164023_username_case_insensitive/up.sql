@@ -0,0 +1,18 @@
+-- usernames that only differ in case have to be sorted out by hand before
+-- uniqueness can ignore case
+DO $$
+DECLARE
+    conflicts TEXT;
+BEGIN
+    SELECT string_agg(usernames, '; ') INTO conflicts FROM (
+        SELECT string_agg(username, ', ' ORDER BY created_at) AS usernames
+        FROM users
+        GROUP BY LOWER(username)
+        HAVING COUNT(*) > 1
+    ) AS duplicates;
+    IF conflicts IS NOT NULL THEN
+        RAISE EXCEPTION 'usernames that only differ in case: %', conflicts
+            USING HINT = 'rename all but one account of each';
+    END IF;
+END $$;
+CREATE UNIQUE INDEX users_username_lower_key ON users(LOWER(username));
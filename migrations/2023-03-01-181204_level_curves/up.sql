@@ -0,0 +1,7 @@
+-- each level curve users' exp has been rebased onto, the newest being the one
+-- they are on now
+CREATE TABLE IF NOT EXISTS level_curves(
+    id SERIAL PRIMARY KEY,
+    curve TEXT NOT NULL,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
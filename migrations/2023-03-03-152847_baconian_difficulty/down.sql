@@ -0,0 +1,2 @@
+DROP INDEX messages_baconian_difficulty_idx;
+ALTER TABLE messages DROP COLUMN baconian_difficulty;
@@ -0,0 +1,5 @@
+-- `difficulty` rates messages as substitution ciphers, which says little about
+-- them as Baconians. Filled in by `cryptopuz backfill-difficulty`
+ALTER TABLE messages ADD COLUMN baconian_difficulty REAL;
+
+CREATE INDEX messages_baconian_difficulty_idx ON messages(baconian_difficulty);
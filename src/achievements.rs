@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use diesel::{
+    dsl::{exists, not},
+    prelude::*,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{
+    api::PuzzleType,
+    error::AppError,
+    models::Achievement,
+    schema::{achievements, solves, user_achievements},
+};
+
+#[repr(i16)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AchievementKind {
+    /// Solve `threshold` puzzles
+    SolveCount = 0,
+    /// Solve a puzzle in under `threshold` milliseconds
+    FastSolve = 1,
+    /// Solve at least one puzzle a day for `threshold` days in a row
+    Streak = 2,
+}
+
+impl TryFrom<i16> for AchievementKind {
+    type Error = AppError;
+
+    fn try_from(v: i16) -> Result<Self, Self::Error> {
+        match v {
+            x if x == AchievementKind::SolveCount as i16 => Ok(AchievementKind::SolveCount),
+            x if x == AchievementKind::FastSolve as i16 => Ok(AchievementKind::FastSolve),
+            x if x == AchievementKind::Streak as i16 => Ok(AchievementKind::Streak),
+            _ => Err(AppError::InternalServerError(anyhow!(
+                "invalid AchievementKind"
+            ))),
+        }
+    }
+}
+
+/// Checks every achievement the user has not unlocked yet against the solve
+/// being submitted, and unlocks the ones that are now met. Only returns the
+/// ones this call unlocked, so a concurrent solve can't award them twice.
+pub async fn unlock(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+    time_taken: i32,
+    streak: i32,
+) -> Result<Vec<Achievement>, AppError> {
+    let locked = achievements::table
+        .filter(not(exists(
+            user_achievements::table
+                .filter(user_achievements::user_id.eq(uid))
+                .filter(user_achievements::achievement_id.eq(achievements::id)),
+        )))
+        .load::<Achievement>(conn)
+        .await?;
+
+    let mut solve_counts: HashMap<Option<i16>, i32> = HashMap::new();
+    let mut unlocked = vec![];
+
+    for achievement in locked {
+        let matches_type = match achievement.puzzle_type {
+            Some(t) => t == puzzle_type as i16,
+            None => true,
+        };
+
+        let met = match AchievementKind::try_from(achievement.kind)? {
+            AchievementKind::SolveCount if matches_type => {
+                let count = match solve_counts.get(&achievement.puzzle_type) {
+                    Some(count) => *count,
+                    None => {
+                        let mut query = solves::table.filter(solves::solver.eq(uid)).into_boxed();
+                        if let Some(t) = achievement.puzzle_type {
+                            query = query.filter(solves::puzzle_type.eq(t));
+                        }
+                        let count = query.count().get_result::<i64>(conn).await? as i32 + 1;
+                        solve_counts.insert(achievement.puzzle_type, count);
+                        count
+                    }
+                };
+                count >= achievement.threshold
+            }
+            AchievementKind::FastSolve if matches_type => time_taken < achievement.threshold,
+            AchievementKind::Streak => streak >= achievement.threshold,
+            _ => false,
+        };
+
+        if met {
+            unlocked.push(achievement);
+        }
+    }
+
+    if unlocked.is_empty() {
+        return Ok(unlocked);
+    }
+
+    let inserted = diesel::insert_into(user_achievements::table)
+        .values(
+            unlocked
+                .iter()
+                .map(|a| {
+                    (
+                        user_achievements::user_id.eq(uid),
+                        user_achievements::achievement_id.eq(a.id),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .on_conflict_do_nothing()
+        .returning(user_achievements::achievement_id)
+        .get_results::<i32>(conn)
+        .await?;
+
+    unlocked.retain(|a| inserted.contains(&a.id));
+    Ok(unlocked)
+}
@@ -0,0 +1,790 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{self, get, post, put},
+    Json, Router,
+};
+use diesel::{dsl::exists, prelude::*};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{Admin, RequireRole},
+    difficulty,
+    error::{AppError, AppResult},
+    import::{self, Format, ImportReport},
+    models::{Message, MessageSuggestion},
+    schema::{
+        message_ratings, message_suggestions, message_tags, messages, puzzle_attempts,
+        shared_puzzles, solves, tags, test_questions, users,
+    },
+    AppState,
+};
+
+use super::{
+    suggestions::{SuggestionResponse, SuggestionStatus},
+    tags as tag, PuzzleType,
+};
+
+/// Column limits of `messages`, in characters.
+pub const MAX_MESSAGE_LENGTH: usize = 255;
+pub const MAX_ATTRIBUTION_LENGTH: usize = 127;
+pub const MAX_HINT_LENGTH: usize = 255;
+pub const MAX_LANGUAGE_LENGTH: usize = 16;
+
+/// Column limit of `message_suggestions.reason`, in characters.
+const MAX_REASON_LENGTH: usize = 255;
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageResponse {
+    id: i32,
+    message: String,
+    attribution: Option<String>,
+    patristocrat_hint: Option<String>,
+    difficulty: Option<f32>,
+    language: String,
+    deleted_at: Option<String>,
+    tags: Vec<String>,
+}
+
+impl MessageResponse {
+    fn new(message: Message, tags: Vec<String>) -> Self {
+        Self {
+            id: message.id,
+            message: message.message,
+            attribution: message.attribution,
+            patristocrat_hint: message.patristocrat_hint,
+            difficulty: message.difficulty,
+            language: message.language,
+            deleted_at: message
+                .deleted_at
+                .map(|t| format!("{}", t.format("%F %I:%M %P"))),
+            tags,
+        }
+    }
+
+    async fn load(conn: &mut AsyncPgConnection, message: Message) -> QueryResult<Self> {
+        let mut tags = tag::of_messages(conn, &[message.id]).await?;
+        let tags = tags.remove(&message.id).unwrap_or_default();
+        Ok(Self::new(message, tags))
+    }
+}
+
+/// A message's fields after trimming, with empty optional fields left out.
+pub struct MessageFields {
+    pub message: String,
+    pub attribution: Option<String>,
+    pub patristocrat_hint: Option<String>,
+    /// A language tag like `en`
+    pub language: String,
+}
+
+fn non_empty(field: &str) -> Option<String> {
+    let field = field.trim();
+    (!field.is_empty()).then(|| field.to_string())
+}
+
+impl MessageFields {
+    pub fn new(
+        message: &str,
+        attribution: Option<&str>,
+        patristocrat_hint: Option<&str>,
+        language: Option<&str>,
+    ) -> Self {
+        Self {
+            message: message.trim().to_string(),
+            attribution: attribution.and_then(non_empty),
+            patristocrat_hint: patristocrat_hint.and_then(non_empty),
+            language: language
+                .and_then(non_empty)
+                .unwrap_or(DEFAULT_LANGUAGE.to_string())
+                .to_lowercase(),
+        }
+    }
+
+    /// Checks the fields fit in their columns.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.message.chars().any(|c| c.is_ascii_alphabetic()) {
+            return Err("message must have at least 1 letter".to_string());
+        }
+        if !self
+            .language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(format!("{} is not a language tag", self.language));
+        }
+
+        let too_long = |name, field: &str, max| {
+            (field.chars().count() > max).then(|| format!("{name} can be at most {max} characters"))
+        };
+        let error = too_long("message", &self.message, MAX_MESSAGE_LENGTH)
+            .or_else(|| {
+                too_long(
+                    "attribution",
+                    self.attribution.as_deref().unwrap_or_default(),
+                    MAX_ATTRIBUTION_LENGTH,
+                )
+            })
+            .or_else(|| {
+                too_long(
+                    "patristocrat hint",
+                    self.patristocrat_hint.as_deref().unwrap_or_default(),
+                    MAX_HINT_LENGTH,
+                )
+            })
+            .or_else(|| too_long("language", &self.language, MAX_LANGUAGE_LENGTH));
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListRequest {
+    /// Searches the message and attribution
+    q: Option<String>,
+    #[serde(default)]
+    include_deleted: bool,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct ListResponse {
+    total: i64,
+    messages: Vec<MessageResponse>,
+}
+
+async fn list(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Query(req): Query<ListRequest>,
+) -> AppResult<Json<ListResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let pattern = req.q.as_deref().map(|q| {
+        format!(
+            "%{}%",
+            q.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        )
+    });
+    let filtered = || {
+        let mut query = messages::table.into_boxed();
+        if !req.include_deleted {
+            query = query.filter(messages::deleted_at.is_null());
+        }
+        if let Some(pattern) = &pattern {
+            query = query.filter(
+                messages::message
+                    .ilike(pattern.clone())
+                    .or(messages::attribution.ilike(pattern.clone())),
+            );
+        }
+        query
+    };
+
+    let total = filtered().count().get_result::<i64>(conn).await?;
+    let messages = filtered()
+        .order(messages::id)
+        .limit(req.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+        .offset(req.offset.unwrap_or(0).max(0))
+        .load::<Message>(conn)
+        .await?;
+    let ids = messages.iter().map(|m| m.id).collect::<Vec<_>>();
+    let mut tags = tag::of_messages(conn, &ids).await?;
+
+    Ok(Json(ListResponse {
+        total,
+        messages: messages
+            .into_iter()
+            .map(|m| {
+                let tags = tags.remove(&m.id).unwrap_or_default();
+                MessageResponse::new(m, tags)
+            })
+            .collect(),
+    }))
+}
+
+async fn find(conn: &mut AsyncPgConnection, id: i32) -> AppResult<Message> {
+    messages::table
+        .find(id)
+        .first::<Message>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "Message not found"))
+}
+
+async fn message(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Path(id): Path<i32>,
+) -> AppResult<Json<MessageResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let message = find(conn, id).await?;
+    Ok(Json(MessageResponse::load(conn, message).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateRequest {
+    message: String,
+    attribution: Option<String>,
+    patristocrat_hint: Option<String>,
+    /// Defaults to English
+    language: Option<String>,
+}
+
+fn already_exists() -> AppError {
+    AppError::from(StatusCode::CONFLICT, "message already exists")
+}
+
+/// Adds a message to the pool, or returns `None` if a live message already has
+/// the same text.
+pub async fn insert(
+    conn: &mut AsyncPgConnection,
+    fields: &MessageFields,
+) -> QueryResult<Option<Message>> {
+    diesel::insert_into(messages::table)
+        .values((
+            messages::message.eq(&fields.message),
+            messages::attribution.eq(&fields.attribution),
+            messages::patristocrat_hint.eq(&fields.patristocrat_hint),
+            messages::difficulty.eq(difficulty::rate(PuzzleType::Aristocrat, &fields.message)),
+            messages::baconian_difficulty
+                .eq(difficulty::rate(PuzzleType::Baconian, &fields.message)),
+            messages::language.eq(&fields.language),
+        ))
+        .on_conflict_do_nothing()
+        .get_result::<Message>(conn)
+        .await
+        .optional()
+}
+
+async fn create(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Json(req): Json<CreateRequest>,
+) -> AppResult<(StatusCode, Json<MessageResponse>)> {
+    let fields = MessageFields::new(
+        &req.message,
+        req.attribution.as_deref(),
+        req.patristocrat_hint.as_deref(),
+        req.language.as_deref(),
+    );
+    fields
+        .validate()
+        .map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e))?;
+
+    let conn = &mut state.db_pool.get().await?;
+    let message = insert(conn, &fields).await?.ok_or_else(already_exists)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(MessageResponse::new(message, vec![])),
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EditRequest {
+    message: Option<String>,
+    /// An empty string clears it
+    attribution: Option<String>,
+    /// An empty string clears it
+    patristocrat_hint: Option<String>,
+    language: Option<String>,
+}
+
+/// Whether anything recorded refers to the message's text, so changing it
+/// would rewrite history.
+async fn is_referenced(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<bool> {
+    diesel::select(
+        exists(solves::table.filter(solves::message_id.eq(id)))
+            .or(exists(
+                test_questions::table.filter(test_questions::message_id.eq(id)),
+            ))
+            .or(exists(
+                shared_puzzles::table.filter(shared_puzzles::message_id.eq(id)),
+            ))
+            .or(exists(
+                puzzle_attempts::table.filter(puzzle_attempts::message_id.eq(id)),
+            ))
+            .or(exists(
+                message_ratings::table.filter(message_ratings::message_id.eq(id)),
+            ))
+            .or(exists(
+                message_suggestions::table.filter(message_suggestions::message_id.eq(id)),
+            )),
+    )
+    .get_result::<bool>(conn)
+    .await
+}
+
+/// Edits a live message. Changing the text of a message that has been solved
+/// deletes it and adds the new text as a new message instead, so past solves
+/// keep showing what was actually solved. The response has the new id, and
+/// the new message keeps the old one's tags.
+async fn edit(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Json(req): Json<EditRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let old = find(conn, id).await?;
+    if old.deleted_at.is_some() {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "restore the message before editing it",
+        ));
+    }
+
+    let fields = MessageFields::new(
+        req.message.as_deref().unwrap_or(&old.message),
+        req.attribution.as_deref().or(old.attribution.as_deref()),
+        req.patristocrat_hint
+            .as_deref()
+            .or(old.patristocrat_hint.as_deref()),
+        Some(req.language.as_deref().unwrap_or(&old.language)),
+    );
+    fields
+        .validate()
+        .map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e))?;
+
+    if fields.message != old.message && is_referenced(conn, id).await? {
+        let message = conn
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                async move {
+                    diesel::update(messages::table.find(id))
+                        .set(messages::deleted_at.eq(diesel::dsl::now))
+                        .execute(conn)
+                        .await?;
+                    let Some(message) = insert(conn, &fields).await? else {
+                        return Ok(None);
+                    };
+                    let tag_ids = message_tags::table
+                        .select(message_tags::tag_id)
+                        .filter(message_tags::message_id.eq(id))
+                        .load::<i32>(conn)
+                        .await?;
+                    diesel::insert_into(message_tags::table)
+                        .values(
+                            tag_ids
+                                .iter()
+                                .map(|tag_id| {
+                                    (
+                                        message_tags::tag_id.eq(tag_id),
+                                        message_tags::message_id.eq(message.id),
+                                    )
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                        .execute(conn)
+                        .await?;
+                    Ok(Some(message))
+                }
+                .boxed()
+            })
+            .await?;
+
+        let message = message.ok_or_else(already_exists)?;
+        return Ok(Json(MessageResponse::load(conn, message).await?));
+    }
+
+    let message = diesel::update(messages::table.find(id))
+        .set((
+            messages::message.eq(&fields.message),
+            messages::attribution.eq(&fields.attribution),
+            messages::patristocrat_hint.eq(&fields.patristocrat_hint),
+            messages::difficulty.eq(difficulty::rate(PuzzleType::Aristocrat, &fields.message)),
+            messages::baconian_difficulty
+                .eq(difficulty::rate(PuzzleType::Baconian, &fields.message)),
+            messages::language.eq(&fields.language),
+        ))
+        .get_result::<Message>(conn)
+        .await
+        .optional();
+
+    match message {
+        Ok(Some(message)) => Ok(Json(MessageResponse::load(conn, message).await?)),
+        Ok(None) => Err(AppError::from(StatusCode::NOT_FOUND, "Message not found")),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(already_exists()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Takes a message out of the pool. It is kept for the solves that reference
+/// it and can be restored.
+async fn delete(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Path(id): Path<i32>,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let deleted = diesel::update(messages::table.find(id))
+        .filter(messages::deleted_at.is_null())
+        .set(messages::deleted_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await?;
+
+    if deleted == 0 {
+        return Err(AppError::from(StatusCode::NOT_FOUND, "Message not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn restore(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Path(id): Path<i32>,
+) -> AppResult<Json<MessageResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let message = diesel::update(messages::table.find(id))
+        .set(messages::deleted_at.eq(None::<chrono::DateTime<chrono::Local>>))
+        .get_result::<Message>(conn)
+        .await
+        .optional();
+
+    match message {
+        Ok(Some(message)) => Ok(Json(MessageResponse::load(conn, message).await?)),
+        Ok(None) => Err(AppError::from(StatusCode::NOT_FOUND, "Message not found")),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(already_exists()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportRequest {
+    /// `csv` or `jsonl`. Detected from the body if left out.
+    format: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Bulk imports messages from a CSV or JSON lines body, skipping near
+/// duplicates, and reports what happened to each row.
+async fn import_messages(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Query(req): Query<ImportRequest>,
+    body: String,
+) -> AppResult<Json<ImportReport>> {
+    let format = match req.format {
+        Some(format) => format
+            .parse::<Format>()
+            .map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e.to_string()))?,
+        None => Format::detect(&body),
+    };
+
+    let conn = &mut state.db_pool.get().await?;
+    let report = import::import(conn, import::parse(format, &body), req.dry_run).await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+struct TagsRequest {
+    tags: Vec<String>,
+}
+
+/// Replaces a message's tags, creating any tags that don't exist yet.
+async fn set_tags(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Json(req): Json<TagsRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let mut names = req
+        .tags
+        .iter()
+        .map(|name| tag::normalize(name))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e))?;
+    names.sort();
+    names.dedup();
+
+    let conn = &mut state.db_pool.get().await?;
+    let message = find(conn, id).await?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            diesel::insert_into(tags::table)
+                .values(
+                    names
+                        .iter()
+                        .map(|name| tags::name.eq(name))
+                        .collect::<Vec<_>>(),
+                )
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+            let tag_ids = tags::table
+                .select(tags::id)
+                .filter(tags::name.eq_any(&names))
+                .load::<i32>(conn)
+                .await?;
+
+            diesel::delete(message_tags::table.filter(message_tags::message_id.eq(id)))
+                .execute(conn)
+                .await?;
+            diesel::insert_into(message_tags::table)
+                .values(
+                    tag_ids
+                        .iter()
+                        .map(|tag_id| {
+                            (
+                                message_tags::tag_id.eq(tag_id),
+                                message_tags::message_id.eq(id),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+
+    Ok(Json(MessageResponse::load(conn, message).await?))
+}
+
+#[derive(Deserialize)]
+struct CreateTagRequest {
+    name: String,
+}
+
+async fn create_tag(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Json(req): Json<CreateTagRequest>,
+) -> AppResult<StatusCode> {
+    let name = tag::normalize(&req.name).map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e))?;
+
+    let conn = &mut state.db_pool.get().await?;
+    let created = diesel::insert_into(tags::table)
+        .values(tags::name.eq(&name))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+
+    if created == 0 {
+        return Err(AppError::from(StatusCode::CONFLICT, "tag already exists"));
+    }
+    Ok(StatusCode::CREATED)
+}
+
+/// Deletes a tag and takes it off every message that had it.
+async fn delete_tag(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Path(name): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let deleted = diesel::delete(tags::table.filter(tags::name.eq(name.to_lowercase())))
+        .execute(conn)
+        .await?;
+
+    if deleted == 0 {
+        return Err(AppError::from(StatusCode::NOT_FOUND, "Tag not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct SuggestionsRequest {
+    /// Defaults to the ones waiting for review
+    status: Option<SuggestionStatus>,
+}
+
+/// Suggested messages, oldest first.
+async fn suggestions(
+    State(state): State<AppState>,
+    _: RequireRole<Admin>,
+    Query(req): Query<SuggestionsRequest>,
+) -> AppResult<Json<Vec<SuggestionResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let status = req.status.unwrap_or(SuggestionStatus::Pending);
+    let suggestions = message_suggestions::table
+        .inner_join(users::table)
+        .select((message_suggestions::all_columns, users::username))
+        .filter(message_suggestions::status.eq(status as i16))
+        .order(message_suggestions::submitted_at)
+        .load::<(MessageSuggestion, String)>(conn)
+        .await?;
+
+    Ok(Json(
+        suggestions
+            .into_iter()
+            .map(|(suggestion, submitted_by)| SuggestionResponse::new(suggestion, submitted_by))
+            .collect::<AppResult<_>>()?,
+    ))
+}
+
+async fn pending_suggestion(conn: &mut AsyncPgConnection, id: i32) -> AppResult<MessageSuggestion> {
+    let suggestion = message_suggestions::table
+        .find(id)
+        .first::<MessageSuggestion>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "Suggestion not found"))?;
+
+    if suggestion.status != SuggestionStatus::Pending as i16 {
+        return Err(already_reviewed());
+    }
+    Ok(suggestion)
+}
+
+fn already_reviewed() -> AppError {
+    AppError::from(StatusCode::CONFLICT, "suggestion was already reviewed")
+}
+
+/// Fixes to make to a suggestion as it is approved.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApproveRequest {
+    message: Option<String>,
+    attribution: Option<String>,
+    patristocrat_hint: Option<String>,
+    language: Option<String>,
+}
+
+/// Adds a suggested message to the pool, crediting whoever suggested it.
+async fn approve(
+    State(state): State<AppState>,
+    RequireRole(claims, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    req: Option<Json<ApproveRequest>>,
+) -> AppResult<(StatusCode, Json<MessageResponse>)> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let suggestion = pending_suggestion(conn, id).await?;
+    let req = req.map(|Json(req)| req);
+    let req = req.as_ref();
+    let fields = MessageFields::new(
+        req.and_then(|r| r.message.as_deref())
+            .unwrap_or(&suggestion.message),
+        req.and_then(|r| r.attribution.as_deref())
+            .or(suggestion.attribution.as_deref()),
+        req.and_then(|r| r.patristocrat_hint.as_deref()),
+        req.and_then(|r| r.language.as_deref()),
+    );
+    fields
+        .validate()
+        .map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e))?;
+
+    // Only the review that moves it out of pending goes on to add the message
+    let message = conn
+        .transaction::<_, AppError, _>(|conn| {
+            async move {
+                let reviewed = diesel::update(message_suggestions::table.find(id))
+                    .filter(message_suggestions::status.eq(SuggestionStatus::Pending as i16))
+                    .set((
+                        message_suggestions::status.eq(SuggestionStatus::Approved as i16),
+                        message_suggestions::reviewed_by.eq(&claims.uid),
+                        message_suggestions::reviewed_at.eq(diesel::dsl::now),
+                    ))
+                    .execute(conn)
+                    .await?;
+                if reviewed == 0 {
+                    return Err(already_reviewed());
+                }
+
+                let Some(message) = insert(conn, &fields).await? else {
+                    return Err(already_exists());
+                };
+                diesel::update(message_suggestions::table.find(id))
+                    .set(message_suggestions::message_id.eq(message.id))
+                    .execute(conn)
+                    .await?;
+                Ok(message)
+            }
+            .boxed()
+        })
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(MessageResponse::new(message, vec![])),
+    ))
+}
+
+#[derive(Deserialize)]
+struct RejectRequest {
+    /// Shown to the user who suggested it
+    reason: Option<String>,
+}
+
+async fn reject(
+    State(state): State<AppState>,
+    RequireRole(claims, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Json(req): Json<RejectRequest>,
+) -> AppResult<StatusCode> {
+    let reason = req.reason.as_deref().and_then(non_empty);
+    if matches!(&reason, Some(r) if r.chars().count() > MAX_REASON_LENGTH) {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            format!("reason can be at most {MAX_REASON_LENGTH} characters"),
+        ));
+    }
+
+    let conn = &mut state.db_pool.get().await?;
+    pending_suggestion(conn, id).await?;
+
+    let reviewed = diesel::update(message_suggestions::table.find(id))
+        .filter(message_suggestions::status.eq(SuggestionStatus::Pending as i16))
+        .set((
+            message_suggestions::status.eq(SuggestionStatus::Rejected as i16),
+            message_suggestions::reviewed_by.eq(&claims.uid),
+            message_suggestions::reviewed_at.eq(diesel::dsl::now),
+            message_suggestions::reason.eq(reason),
+        ))
+        .execute(conn)
+        .await?;
+    if reviewed == 0 {
+        return Err(already_reviewed());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/messages", get(list).post(create))
+        .route("/messages/:id", get(message).patch(edit).delete(delete))
+        .route("/messages/import", post(import_messages))
+        .route("/messages/:id/restore", post(restore))
+        .route("/messages/:id/tags", put(set_tags))
+        .route("/tags", post(create_tag))
+        .route("/tags/:name", routing::delete(delete_tag))
+        .route("/suggestions", get(suggestions))
+        .route("/suggestions/:id/approve", post(approve))
+        .route("/suggestions/:id/reject", post(reject))
+}
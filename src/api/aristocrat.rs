@@ -1,29 +1,34 @@
 use std::collections::HashMap;
 
 use axum::{
-    extract::State,
+    extract::{Path, Query, State},
     http::StatusCode,
     routing::{get, post},
     Json, Router,
 };
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    api::{NewSolve, PuzzleType},
+    api::{
+        pick_message, record_solve, seeded_message, signed_text, Avoid, NewRequest, PuzzleType,
+        SeedRequest, Solved,
+    },
+    attempts,
     auth::Auth,
     error::{AppError, AppResult},
-    exp::ExpSource,
-    models::User,
-    util::{generate_sig, get_timestamp, random, verify_solution},
+    exp::{self, ExpSource},
+    models::Message,
+    scoring,
+    util::{generate_sig, get_timestamp, key_rng, verify_sig},
     AppState,
 };
-use anyhow::anyhow;
 
-use super::{profile::ProfileResponse, SubmitResponse};
+use super::SubmitResponse;
 
 type SubAlphabet = HashMap<char, char>;
 
@@ -33,50 +38,127 @@ const ALPHABET: [char; 26] = [
 ];
 
 #[derive(Serialize)]
-struct NewResponse {
+pub struct NewResponse {
     id: i32,
     ciphertext: String, // TODO return as an array instead
     sig: String,
     timestamp: u128,
     attribution: String,
+    difficulty: Option<f32>,
+    /// Chooses the key. `/seed/:seed?id=` with this and `id` serves the same
+    /// puzzle again
+    seed: u32,
 }
 
-fn random_sub_alphabet() -> SubAlphabet {
-    let mut rng = thread_rng();
+fn random_sub_alphabet(rng: &mut impl Rng) -> SubAlphabet {
     let mut shuffled = ALPHABET.clone();
-    shuffled.shuffle(&mut rng);
+    shuffled.shuffle(rng);
     ALPHABET.zip(shuffled).into_iter().collect()
 }
 
-async fn new(State(state): State<AppState>, auth: Option<Auth>) -> AppResult<Json<NewResponse>> {
-    use crate::schema::messages;
+/// Encrypts a message with a substitution alphabet drawn from `rng`.
+pub fn encrypt(rng: &mut impl Rng, message: &str) -> String {
+    let sub_alphabet = random_sub_alphabet(rng);
+
+    message
+        .chars()
+        .map(|c| *sub_alphabet.get(&c.to_ascii_lowercase()).unwrap_or(&c))
+        .collect()
+}
+
+/// Serves a random puzzle, avoiding the ones the user has solved or was
+/// served recently until there are no others left.
+pub async fn new(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Query(req): Query<NewRequest>,
+) -> AppResult<Json<NewResponse>> {
     let conn = &mut state.db_pool.get().await?;
+    let recent = match &auth {
+        Some(Auth(claims)) => {
+            attempts::recent_messages(conn, &claims.uid, PuzzleType::Aristocrat).await?
+        }
+        None => vec![],
+    };
+    let avoid = Avoid {
+        solved_by: auth.as_ref().map(|Auth(claims)| claims.uid.as_str()),
+        messages: &recent,
+    };
 
-    let Some((msg_id, message, attribution)) = messages::table
-        .select((messages::id, messages::message, messages::attribution))
-        .order(random())
-        .first::<(i32, String, Option<String>)>(conn)
-        .await
-        .optional()? else {
-            return Err(anyhow!("expected 1 message in database").into())
-        };
+    // What gets picked depends on what the user has seen, so it is replayed
+    // by its id rather than the seed
+    let message = pick_message(
+        conn,
+        &mut ChaCha8Rng::from_entropy(),
+        PuzzleType::Aristocrat,
+        &auth,
+        &req,
+        avoid,
+    )
+    .await?;
+    let seed = thread_rng().gen();
 
-    let sub_alphabet = random_sub_alphabet();
+    Ok(Json(serve(&state, conn, &auth, message, seed).await?))
+}
 
-    let ciphertext: String = message
-        .chars()
-        .map(|c| *sub_alphabet.get(&c.to_ascii_lowercase()).unwrap_or(&c))
-        .collect();
+/// Regenerates the puzzle `new` served with this seed, given its `id`. Without
+/// an id, the seed picks the message from the whole pool, so everyone with the
+/// same seed and filters gets the same puzzle.
+pub async fn seeded(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Path(seed): Path<u32>,
+    Query(seed_req): Query<SeedRequest>,
+    Query(req): Query<NewRequest>,
+) -> AppResult<Json<NewResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let message =
+        seeded_message(conn, seed, PuzzleType::Aristocrat, &auth, &seed_req, &req).await?;
+
+    Ok(Json(serve(&state, conn, &auth, message, seed).await?))
+}
+
+/// Encrypts `message` with the key generated from `seed` and signs it for the
+/// user.
+pub async fn serve(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    auth: &Option<Auth>,
+    message: Message,
+    seed: u32,
+) -> AppResult<NewResponse> {
+    let Message {
+        id: msg_id,
+        message,
+        attribution,
+        difficulty,
+        ..
+    } = message;
+
+    // The key gets its own rng so it only depends on the seed, not on how many
+    // messages there were to pick from
+    let ciphertext = encrypt(&mut key_rng(seed), &message);
 
     let timestamp = get_timestamp();
+    if let Some(Auth(claims)) = auth {
+        attempts::start(conn, &claims.uid, PuzzleType::Aristocrat, msg_id, timestamp).await?;
+    }
 
-    Ok(Json(NewResponse {
+    Ok(NewResponse {
         id: msg_id,
         ciphertext,
-        sig: generate_sig(&state.hmac_key, &auth, msg_id, timestamp, message),
+        sig: generate_sig(
+            &state.hmac_key,
+            auth,
+            msg_id,
+            timestamp,
+            signed_text(seed, &message),
+        ),
         timestamp,
         attribution: attribution.unwrap_or("Unknown".to_string()),
-    }))
+        difficulty,
+        seed,
+    })
 }
 
 #[derive(Deserialize)]
@@ -85,6 +167,9 @@ struct SubmitRequest {
     message: String,
     sig: String,
     timestamp: u128,
+    seed: u32,
+    /// The code of the shared link the puzzle came from, if any
+    share: Option<String>,
 }
 
 async fn submit(
@@ -92,20 +177,39 @@ async fn submit(
     auth: Option<Auth>,
     Json(req): Json<SubmitRequest>,
 ) -> AppResult<Json<SubmitResponse>> {
-    use crate::schema::{messages, solves, users};
+    use crate::schema::messages;
     let conn = &mut state.db_pool.get().await?;
 
-    if verify_solution(
+    let Some((plaintext, difficulty)) = messages::table
+        .select((messages::message, messages::difficulty))
+        .filter(messages::id.eq(req.id))
+        .first::<(String, Option<f32>)>(conn)
+        .await
+        .optional()? else {
+            return Err(AppError::from(StatusCode::BAD_REQUEST, "invalid puzzle"));
+        };
+
+    if !verify_sig(
         &state.hmac_key,
         &auth,
         req.id,
         req.timestamp,
-        req.message,
+        signed_text(req.seed, &plaintext),
         req.sig,
     )? {
+        return Err(AppError::from(StatusCode::BAD_REQUEST, "invalid puzzle"));
+    }
+
+    let grade = scoring::grade(
+        PuzzleType::Aristocrat,
+        &plaintext,
+        &req.message,
+        scoring::points(PuzzleType::Aristocrat),
+    );
+    if grade.score > 0 {
         let time_taken = get_timestamp() - req.timestamp;
         if let Some(Auth(claims)) = auth {
-            let solve_exp = 100;
+            let solve_exp = exp::scale_by_difficulty(100, difficulty);
             let time_taken_sec = (time_taken as f64) / 1000.0;
             let time_bonus =
                 (100_f64 - ((time_taken_sec - 10.0).max(0.0) * 5.0 / 3.0)).max(0.0) as i32;
@@ -117,46 +221,29 @@ async fn submit(
                 exp_sources.push(ExpSource::additive("Time Bonus", time_bonus));
             }
 
-            // TODO total and save to table to display recent solves
-            let user = diesel::update(users::table)
-                .filter(users::id.eq(claims.uid))
-                .set((
-                    users::experience.eq(users::experience + sum),
-                    users::solved.eq(users::solved + 1),
-                ))
-                .get_result::<User>(conn)
-                .await?;
-
-            diesel::insert_into(solves::table)
-                .values(NewSolve::new(
-                    PuzzleType::Aristocrat,
-                    req.id,
-                    &user,
-                    time_taken as i32,
+            return Ok(Json(
+                record_solve(
+                    conn,
+                    &claims.uid,
+                    Solved {
+                        puzzle_type: PuzzleType::Aristocrat,
+                        message_id: req.id,
+                        seed: req.seed,
+                        share: req.share,
+                        timestamp: req.timestamp,
+                        time_taken,
+                        grade,
+                    },
+                    exp_sources,
                     sum,
-                ))
-                .execute(conn)
-                .await?;
-
-            return Ok(Json(SubmitResponse {
-                plaintext: messages::table
-                    .select(messages::message)
-                    .filter(messages::id.eq(req.id))
-                    .first::<String>(conn)
-                    .await?,
-                time_taken,
-                profile: Some(ProfileResponse::from(user)),
-                exp_sources: Some(exp_sources),
-                total_exp: Some(sum),
-            }));
+                )
+                .await?,
+            ));
         } else {
             return Ok(Json(SubmitResponse {
-                plaintext: messages::table
-                    .select(messages::message)
-                    .filter(messages::id.eq(req.id))
-                    .first::<String>(conn)
-                    .await?,
+                plaintext,
                 time_taken,
+                grade,
                 profile: None,
                 exp_sources: None,
                 total_exp: None,
@@ -164,6 +251,17 @@ async fn submit(
         }
     }
 
+    if let Some(Auth(claims)) = &auth {
+        attempts::record_wrong(
+            conn,
+            &claims.uid,
+            PuzzleType::Aristocrat,
+            req.id,
+            req.timestamp,
+        )
+        .await?;
+    }
+
     Err(AppError::from(
         StatusCode::EXPECTATION_FAILED,
         "The puzzle is incorrect",
@@ -173,5 +271,6 @@ async fn submit(
 pub fn app() -> Router<AppState> {
     Router::new()
         .route("/new", get(new))
+        .route("/seed/:seed", get(seeded))
         .route("/submit", post(submit))
 }
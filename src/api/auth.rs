@@ -1,31 +1,94 @@
-use std::time::Duration;
+use std::time::Duration as StdDuration;
 
+use anyhow::anyhow;
 use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
-use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use chrono::{DateTime, Duration, Local};
+use diesel::{dsl::exists, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    auth,
-    error::{AppError, AppResult},
+    auth::{self, Auth},
+    error::{AppError, AppResult, ValidationErrors},
+    mail::{self, Mail},
     models::User,
-    schema::users,
-    AppState,
+    rate_limit::{self, ClientIp, Limit},
+    schema::{email_verifications, password_resets, users},
+    sessions::{self, Refreshed},
+    util::{hash_token, lower, new_secret_token},
+    validation, AppState,
+};
+
+/// How long a password reset link works for.
+const RESET_LIFETIME_MINUTES: i64 = 60;
+
+/// How long an email verification link works for.
+const VERIFICATION_LIFETIME_HOURS: i64 = 24;
+
+/// Failed logins to one account, from anywhere.
+const LOGIN_USERNAME_LIMIT: Limit = Limit {
+    attempts: 5,
+    window: StdDuration::from_secs(15 * 60),
+    lockout: StdDuration::from_secs(60),
+    max_lockout: StdDuration::from_secs(60 * 60),
+};
+
+/// Failed logins from one IP, to any account.
+const LOGIN_IP_LIMIT: Limit = Limit {
+    attempts: 20,
+    window: StdDuration::from_secs(15 * 60),
+    lockout: StdDuration::from_secs(60),
+    max_lockout: StdDuration::from_secs(60 * 60),
+};
+
+/// Rejected registrations from one IP. Whole classrooms sign up from behind
+/// one address, so accounts that are created don't count.
+const REGISTER_IP_LIMIT: Limit = Limit {
+    attempts: 30,
+    window: StdDuration::from_secs(60 * 60),
+    lockout: StdDuration::from_secs(5 * 60),
+    max_lockout: StdDuration::from_secs(60 * 60),
+};
+
+/// Password reset requests from one IP, for any email.
+const FORGOT_IP_LIMIT: Limit = Limit {
+    attempts: 20,
+    window: StdDuration::from_secs(60 * 60),
+    lockout: StdDuration::from_secs(15 * 60),
+    max_lockout: StdDuration::from_secs(24 * 60 * 60),
+};
+
+/// Password reset requests for one email, from anywhere.
+const FORGOT_EMAIL_LIMIT: Limit = Limit {
+    attempts: 3,
+    window: StdDuration::from_secs(60 * 60),
+    lockout: StdDuration::from_secs(60 * 60),
+    max_lockout: StdDuration::from_secs(24 * 60 * 60),
 };
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct UserAuthorizedResponse {
+    /// Short lived, sent with each request
     token: String,
+    /// Swapped for a new token and refresh token when the token expires
+    refresh_token: String,
 }
 
 impl UserAuthorizedResponse {
-    fn from_user(user: &User) -> anyhow::Result<Self> {
+    fn new(user: &User, sid: &str, refresh_token: String) -> anyhow::Result<Self> {
         Ok(Self {
-            token: auth::generate_jwt(user, Duration::from_secs(24 * 60 * 60))?,
+            token: auth::generate_jwt(user, sid, sessions::ACCESS_TOKEN_LIFETIME)?,
+            refresh_token,
         })
     }
+
+    /// Logs the user in on a new session.
+    async fn start_session(conn: &mut AsyncPgConnection, user: &User) -> AppResult<Self> {
+        let (sid, refresh_token) = sessions::start(conn, &user.id).await?;
+        Ok(Self::new(user, &sid, refresh_token)?)
+    }
 }
 
 #[derive(Deserialize)]
@@ -37,8 +100,28 @@ struct RegisterRequest {
 
 async fn register(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Json(req): Json<RegisterRequest>,
 ) -> AppResult<Json<UserAuthorizedResponse>> {
+    let ip_key = format!("register:ip:{ip}");
+    if let Some(retry_after) = state.limiter.retry_after(&ip_key).await {
+        return Err(rate_limit::too_many_attempts(retry_after));
+    }
+
+    let registered = create_account(&state, req).await;
+    if let Err(AppError::ResponseStatusError(_) | AppError::ValidationError(_)) = &registered {
+        state
+            .limiter
+            .record_failure(&ip_key, &REGISTER_IP_LIMIT)
+            .await;
+    }
+    Ok(Json(registered?))
+}
+
+async fn create_account(
+    state: &AppState,
+    req: RegisterRequest,
+) -> AppResult<UserAuthorizedResponse> {
     #[derive(Insertable)]
     #[diesel(table_name = users)]
     struct NewUser {
@@ -48,33 +131,67 @@ async fn register(
         password_hash: String,
     }
 
-    if req.username.len() > 32 {
-        return Err(AppError::from(StatusCode::BAD_REQUEST, "username too long"));
-    }
+    let mut errors = ValidationErrors::default();
+    validation::check_username(&mut errors, "username", &req.username);
+    validation::check_password(&mut errors, "password", &req.password, &req.username);
+    let email = match req.email.as_deref().map(str::trim) {
+        Some("") | None => None,
+        Some(email) => {
+            let email = mail::normalize_address(email);
+            if email.is_none() {
+                errors.add("email", "is not a valid email address");
+            }
+            email
+        }
+    };
+    errors.check()?;
 
     let conn = &mut state.db_pool.get().await?;
 
+    let mut errors = ValidationErrors::default();
+    if username_taken(conn, &req.username).await? {
+        errors.add("username", USERNAME_TAKEN);
+    }
+    if let Some(email) = &email {
+        if email_in_use(conn, email).await? {
+            errors.add("email", EMAIL_IN_USE);
+        }
+    }
+    errors.check()?;
+
     let new_user = diesel::insert_into(users::table)
         .values(NewUser {
             id: nanoid!(),
             username: req.username,
-            email: req.email,
-            password_hash: auth::hash_password(req.password).unwrap(),
+            email,
+            password_hash: hash_password(&req.password)?,
         })
-        .on_conflict(users::username)
-        .do_nothing()
         .get_result::<User>(conn)
-        .await
-        .optional()?;
+        .await;
 
-    let Some(new_user) = new_user else {
-            return Err(AppError::from(
-                StatusCode::CONFLICT,
-                "username has been taken",
-            ));
-        };
+    let new_user = match new_user {
+        Ok(new_user) => new_user,
+        // Taken by someone else since it was checked
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            info,
+        )) => {
+            return Err(match info.constraint_name() {
+                Some("users_email_key") => email_in_use_error(),
+                _ => username_taken_error(),
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(email) = &new_user.email {
+        // The account exists either way, and the mail can be sent again later
+        if let Err(e) = send_verification(state, conn, &new_user, email).await {
+            log::error!("failed to send verification email: {e:#}");
+        }
+    }
 
-    Ok(Json(UserAuthorizedResponse::from_user(&new_user)?))
+    UserAuthorizedResponse::start_session(conn, &new_user).await
 }
 
 #[derive(Deserialize)]
@@ -83,31 +200,783 @@ struct LoginRequest {
     pub password: String,
 }
 
+/// Checked before the password is, so that locked out attempts don't cost a
+/// hash.
 async fn login(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Json(req): Json<LoginRequest>,
 ) -> AppResult<Json<UserAuthorizedResponse>> {
+    let ip_key = format!("login:ip:{ip}");
+    // Hashed since the username can be anything the client sends, however long
+    let username_key = format!(
+        "login:username:{}",
+        hash_token(&req.username.to_lowercase())
+    );
+    for key in [&ip_key, &username_key] {
+        if let Some(retry_after) = state.limiter.retry_after(key).await {
+            return Err(rate_limit::too_many_attempts(retry_after));
+        }
+    }
+
     let conn = &mut state.db_pool.get().await?;
 
     if let Some(user) = users::table
-        .filter(users::username.eq(req.username))
+        .filter(lower(users::username).eq(lower(&req.username)))
         .first::<User>(conn)
         .await
         .optional()?
     {
         if auth::verify_password(req.password, &user.password_hash).unwrap() {
-            return Ok(Json(UserAuthorizedResponse::from_user(&user)?));
+            state.limiter.clear(&username_key).await;
+            return Ok(Json(
+                UserAuthorizedResponse::start_session(conn, &user).await?,
+            ));
         }
     }
 
+    state.limiter.record_failure(&ip_key, &LOGIN_IP_LIMIT).await;
+    state
+        .limiter
+        .record_failure(&username_key, &LOGIN_USERNAME_LIMIT)
+        .await;
     Err(AppError::from(
         StatusCode::UNAUTHORIZED,
         "invalid username or password",
     ))
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Issues a new token for the session, replacing the refresh token. Each
+/// refresh token only works once.
+async fn refresh(
+    State(state): State<AppState>,
+    Json(req): Json<RefreshRequest>,
+) -> AppResult<Json<UserAuthorizedResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    match sessions::refresh(conn, &req.refresh_token).await? {
+        Refreshed::Rotated { id, uid, token } => {
+            let user = users::table.find(&uid).first::<User>(conn).await?;
+            Ok(Json(UserAuthorizedResponse::new(&user, &id, token)?))
+        }
+        Refreshed::Reused => Err(AppError::from(
+            StatusCode::UNAUTHORIZED,
+            "refresh token was already used, log in again",
+        )),
+        Refreshed::Invalid => Err(AppError::from(
+            StatusCode::UNAUTHORIZED,
+            "invalid or expired refresh token",
+        )),
+    }
+}
+
+async fn logout(State(state): State<AppState>, Auth(claims): Auth) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+    sessions::revoke(conn, &claims.sid).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Logs out every session of the user, including this one.
+async fn logout_all(State(state): State<AppState>, Auth(claims): Auth) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+    sessions::revoke_all(conn, &claims.uid).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn hash_password(password: &str) -> anyhow::Result<String> {
+    auth::hash_password(password).map_err(|e| anyhow!("failed to hash password: {e}"))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> anyhow::Result<bool> {
+    auth::verify_password(password, password_hash)
+        .map_err(|e| anyhow!("failed to verify password: {e}"))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+/// Changes the user's password and logs out their other sessions.
+async fn change_password(
+    State(state): State<AppState>,
+    Auth(claims): Auth,
+    Json(req): Json<ChangePasswordRequest>,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let user = users::table.find(&claims.uid).first::<User>(conn).await?;
+    if !verify_password(&req.current_password, &user.password_hash)? {
+        return Err(AppError::from(
+            StatusCode::FORBIDDEN,
+            "current password is incorrect",
+        ));
+    }
+
+    let mut errors = ValidationErrors::default();
+    validation::check_password(
+        &mut errors,
+        "newPassword",
+        &req.new_password,
+        &user.username,
+    );
+    errors.check()?;
+
+    diesel::update(users::table.find(&user.id))
+        .set(users::password_hash.eq(hash_password(&req.new_password)?))
+        .execute(conn)
+        .await?;
+    sessions::revoke_others(conn, &user.id, &claims.sid).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeEmailRequest {
+    current_password: String,
+    /// Empty to remove the email
+    email: String,
+}
+
+/// Changes the user's email and mails a link to verify the new one. Links sent
+/// to the old email stop working.
+async fn change_email(
+    State(state): State<AppState>,
+    Auth(claims): Auth,
+    Json(req): Json<ChangeEmailRequest>,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let user = users::table.find(&claims.uid).first::<User>(conn).await?;
+    if !verify_password(&req.current_password, &user.password_hash)? {
+        return Err(AppError::from(
+            StatusCode::FORBIDDEN,
+            "current password is incorrect",
+        ));
+    }
+
+    let mut errors = ValidationErrors::default();
+    let email = match req.email.trim() {
+        "" => None,
+        email => {
+            let email = mail::normalize_address(email);
+            if email.is_none() {
+                errors.add("email", "is not a valid email address");
+            }
+            email
+        }
+    };
+    errors.check()?;
+    if email == user.email {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "that is already your email",
+        ));
+    }
+
+    let user = diesel::update(users::table.find(&user.id))
+        .set((
+            users::email.eq(&email),
+            users::email_verified_at.eq(None::<DateTime<Local>>),
+        ))
+        .get_result::<User>(conn)
+        .await;
+    let user = match user {
+        Ok(user) => user,
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => return Err(email_in_use_error()),
+        Err(e) => return Err(e.into()),
+    };
+    diesel::delete(password_resets::table)
+        .filter(password_resets::user_id.eq(&user.id))
+        .filter(password_resets::used_at.is_null())
+        .execute(conn)
+        .await?;
+
+    let Some(email) = &user.email else {
+        return Ok(StatusCode::NO_CONTENT);
+    };
+    // The email is changed either way, and the mail can be sent again later
+    if let Err(e) = send_verification(&state, conn, &user, email).await {
+        log::error!("failed to send verification email: {e:#}");
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+struct ForgotPasswordRequest {
+    email: String,
+}
+
+/// Mails a reset link to the account with the email, if it has been verified.
+/// The response is the same either way, so it can't be used to find accounts.
+/// The work happens after responding, so how long it takes doesn't give it
+/// away either. Every request counts towards the limits, so an address can't
+/// be flooded with mail.
+async fn forgot_password(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(req): Json<ForgotPasswordRequest>,
+) -> AppResult<StatusCode> {
+    let email = req.email.trim().to_lowercase();
+
+    let ip_key = format!("forgot:ip:{ip}");
+    let email_key = format!("forgot:email:{}", hash_token(&email));
+    for key in [&ip_key, &email_key] {
+        if let Some(retry_after) = state.limiter.retry_after(key).await {
+            return Err(rate_limit::too_many_attempts(retry_after));
+        }
+    }
+    state
+        .limiter
+        .record_failure(&ip_key, &FORGOT_IP_LIMIT)
+        .await;
+    state
+        .limiter
+        .record_failure(&email_key, &FORGOT_EMAIL_LIMIT)
+        .await;
+
+    tokio::spawn(async move {
+        if let Err(e) = send_password_reset(&state, email).await {
+            log::error!("failed to send password reset: {e:#}");
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn send_password_reset(state: &AppState, email: String) -> anyhow::Result<()> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let Some((uid, username)) = users::table
+        .select((users::id, users::username))
+        .filter(users::email.eq(&email))
+        .filter(users::email_verified_at.is_not_null())
+        .first::<(String, String)>(conn)
+        .await
+        .optional()? else {
+            return Ok(());
+        };
+
+    // Only the newest link works
+    diesel::delete(password_resets::table)
+        .filter(password_resets::user_id.eq(&uid))
+        .filter(password_resets::used_at.is_null())
+        .execute(conn)
+        .await?;
+
+    let token = new_secret_token();
+    diesel::insert_into(password_resets::table)
+        .values((
+            password_resets::token_hash.eq(hash_token(&token)),
+            password_resets::user_id.eq(&uid),
+            password_resets::expires_at
+                .eq(Local::now() + Duration::minutes(RESET_LIFETIME_MINUTES)),
+        ))
+        .execute(conn)
+        .await?;
+
+    state
+        .mailer
+        .send(&Mail {
+            to: email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Someone asked to reset the password of {username}. If it was you, \
+                 choose a new one at {}/reset-password?token={token}\n\n\
+                 The link works for {RESET_LIFETIME_MINUTES} minutes. If it wasn't \
+                 you, you can ignore this email.",
+                mail::app_url()
+            ),
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResetPasswordRequest {
+    token: String,
+    new_password: String,
+}
+
+/// Sets a new password with the token from a reset link, and logs out every
+/// session in case the account was taken over.
+async fn reset_password(
+    State(state): State<AppState>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+    let token_hash = hash_token(&req.token);
+
+    let Some(username) = password_resets::table
+        .find(&token_hash)
+        .inner_join(users::table)
+        .select(users::username)
+        .filter(password_resets::used_at.is_null())
+        .filter(password_resets::expires_at.gt(diesel::dsl::now))
+        .first::<String>(conn)
+        .await
+        .optional()? else {
+            return Err(invalid_reset_link());
+        };
+
+    let mut errors = ValidationErrors::default();
+    validation::check_password(&mut errors, "newPassword", &req.new_password, &username);
+    errors.check()?;
+    let password_hash = hash_password(&req.new_password)?;
+
+    let Some(uid) = diesel::update(password_resets::table.find(&token_hash))
+        .filter(password_resets::used_at.is_null())
+        .filter(password_resets::expires_at.gt(diesel::dsl::now))
+        .set(password_resets::used_at.eq(diesel::dsl::now))
+        .returning(password_resets::user_id)
+        .get_result::<String>(conn)
+        .await
+        .optional()? else {
+            return Err(invalid_reset_link());
+        };
+
+    diesel::update(users::table.find(&uid))
+        .set(users::password_hash.eq(password_hash))
+        .execute(conn)
+        .await?;
+    sessions::revoke_all(conn, &uid).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn invalid_reset_link() -> AppError {
+    AppError::from(StatusCode::BAD_REQUEST, "invalid or expired reset link")
+}
+
+/// Whether an account has the username, ignoring case.
+async fn username_taken(conn: &mut AsyncPgConnection, username: &str) -> QueryResult<bool> {
+    diesel::select(exists(
+        users::table.filter(lower(users::username).eq(lower(username))),
+    ))
+    .get_result::<bool>(conn)
+    .await
+}
+
+const USERNAME_TAKEN: &str = "has been taken";
+
+fn username_taken_error() -> AppError {
+    let mut errors = ValidationErrors::default();
+    errors.add("username", USERNAME_TAKEN);
+    AppError::ValidationError(errors)
+}
+
+async fn email_in_use(conn: &mut AsyncPgConnection, email: &str) -> QueryResult<bool> {
+    diesel::select(exists(users::table.filter(users::email.eq(email))))
+        .get_result::<bool>(conn)
+        .await
+}
+
+const EMAIL_IN_USE: &str = "is already in use";
+
+fn email_in_use_error() -> AppError {
+    let mut errors = ValidationErrors::default();
+    errors.add("email", EMAIL_IN_USE);
+    AppError::ValidationError(errors)
+}
+
+/// Mails the user a link to verify `email`, replacing any earlier link.
+async fn send_verification(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    email: &str,
+) -> anyhow::Result<()> {
+    diesel::delete(email_verifications::table)
+        .filter(email_verifications::user_id.eq(&user.id))
+        .execute(conn)
+        .await?;
+
+    let token = new_secret_token();
+    diesel::insert_into(email_verifications::table)
+        .values((
+            email_verifications::token_hash.eq(hash_token(&token)),
+            email_verifications::user_id.eq(&user.id),
+            email_verifications::email.eq(email),
+            email_verifications::expires_at
+                .eq(Local::now() + Duration::hours(VERIFICATION_LIFETIME_HOURS)),
+        ))
+        .execute(conn)
+        .await?;
+
+    state
+        .mailer
+        .send(&Mail {
+            to: email.to_string(),
+            subject: "Verify your email".to_string(),
+            body: format!(
+                "Hi {}, confirm this is your email at \
+                 {}/verify-email?token={token}\n\n\
+                 The link works for {VERIFICATION_LIFETIME_HOURS} hours.",
+                user.username,
+                mail::app_url()
+            ),
+        })
+        .await
+}
+
+/// Sends a new verification link to the user's email.
+async fn resend_verification(
+    State(state): State<AppState>,
+    Auth(claims): Auth,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let user = users::table.find(&claims.uid).first::<User>(conn).await?;
+    let Some(email) = &user.email else {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "no email to verify",
+        ));
+    };
+    if user.email_verified_at.is_some() {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "email is already verified",
+        ));
+    }
+
+    send_verification(&state, conn, &user, email).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+struct VerifyEmailRequest {
+    token: String,
+}
+
+async fn verify_email(
+    State(state): State<AppState>,
+    Json(req): Json<VerifyEmailRequest>,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let Some((uid, email)) =
+        diesel::delete(email_verifications::table.find(hash_token(&req.token)))
+            .filter(email_verifications::expires_at.gt(diesel::dsl::now))
+            .returning((email_verifications::user_id, email_verifications::email))
+            .get_result::<(String, String)>(conn)
+            .await
+            .optional()? else {
+            return Err(AppError::from(
+                StatusCode::BAD_REQUEST,
+                "invalid or expired verification link",
+            ));
+        };
+
+    let verified = diesel::update(users::table.find(&uid))
+        .filter(users::email.eq(&email))
+        .set(users::email_verified_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await?;
+    if verified == 0 {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "email has changed since the link was sent",
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub fn app() -> Router<AppState> {
     Router::new()
         .route("/register", post(register))
         .route("/login", post(login))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
+        .route("/logout/all", post(logout_all))
+        .route("/password", post(change_password))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
+        .route("/email", post(change_email))
+        .route("/email/resend", post(resend_verification))
+        .route("/email/verify", post(verify_email))
+}
+
+/// These need an empty database in `TEST_DATABASE_URL`, which they migrate the
+/// first time. Run them with `cargo test -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, sync::Arc};
+
+    use axum::response::IntoResponse;
+    use diesel::{dsl::sql, sql_types::Bool};
+    use diesel_async::{AsyncConnection, SimpleAsyncConnection};
+    use ring::hmac;
+    use tokio::sync::OnceCell;
+
+    use super::*;
+    use crate::{
+        auth::{Claims, Role},
+        establish_connection,
+        mail::CaptureMailer,
+        rate_limit::MemoryLimitStore,
+    };
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    static MIGRATED: OnceCell<()> = OnceCell::const_new();
+
+    async fn setup() -> (AppState, Arc<CaptureMailer>) {
+        let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        MIGRATED
+            .get_or_init(|| async {
+                let conn = &mut AsyncPgConnection::establish(&url).await.unwrap();
+                let migrated = diesel::select(sql::<Bool>("to_regclass('users') IS NOT NULL"))
+                    .get_result::<bool>(conn)
+                    .await
+                    .unwrap();
+                if migrated {
+                    return;
+                }
+
+                let mut dirs = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .collect::<Vec<_>>();
+                dirs.sort();
+                for dir in dirs {
+                    let up = fs::read_to_string(dir.join("up.sql")).unwrap();
+                    conn.batch_execute(&up).await.unwrap();
+                }
+            })
+            .await;
+
+        let mailer = Arc::new(CaptureMailer::default());
+        let state = AppState {
+            db_pool: establish_connection(&url),
+            hmac_key: hmac::Key::new(hmac::HMAC_SHA256, b"test"),
+            mailer: mailer.clone(),
+            limiter: Arc::new(MemoryLimitStore::default()),
+        };
+        (state, mailer)
+    }
+
+    fn new_email() -> String {
+        format!("{}@example.com", nanoid!(12).to_lowercase())
+    }
+
+    async fn create_user(state: &AppState, email: &str, verified: bool) -> User {
+        let conn = &mut state.db_pool.get().await.unwrap();
+        diesel::insert_into(users::table)
+            .values((
+                users::id.eq(nanoid!()),
+                users::username.eq(nanoid!(16)),
+                users::email.eq(email),
+                users::password_hash.eq(hash_password(PASSWORD).unwrap()),
+                users::email_verified_at.eq(verified.then(Local::now)),
+            ))
+            .get_result::<User>(conn)
+            .await
+            .unwrap()
+    }
+
+    async fn reload(state: &AppState, user: &User) -> User {
+        let conn = &mut state.db_pool.get().await.unwrap();
+        users::table.find(&user.id).first(conn).await.unwrap()
+    }
+
+    fn auth(user: &User) -> Auth {
+        Auth(Claims {
+            uid: user.id.clone(),
+            username: user.username.clone(),
+            sid: "test".to_string(),
+            role: Role::default(),
+            iat: 0,
+            exp: 0,
+        })
+    }
+
+    fn status(result: AppResult<StatusCode>) -> StatusCode {
+        result.unwrap_or_else(|e| e.into_response().status())
+    }
+
+    /// The token in the newest link mailed to `to`.
+    fn last_token(mailer: &CaptureMailer, to: &str) -> String {
+        let mail = mailer.sent_to(to).pop().expect("no mail was sent");
+        let (_, link) = mail.body.split_once("token=").expect("no link in the mail");
+        link.split_whitespace().next().unwrap().to_string()
+    }
+
+    async fn verify(state: &AppState, token: &str) -> StatusCode {
+        status(
+            verify_email(
+                State(state.clone()),
+                Json(VerifyEmailRequest {
+                    token: token.to_string(),
+                }),
+            )
+            .await,
+        )
+    }
+
+    async fn change(state: &AppState, user: &User, password: &str, email: &str) -> StatusCode {
+        status(
+            change_email(
+                State(state.clone()),
+                auth(user),
+                Json(ChangeEmailRequest {
+                    current_password: password.to_string(),
+                    email: email.to_string(),
+                }),
+            )
+            .await,
+        )
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn verification_links_work_once() {
+        let (state, mailer) = setup().await;
+        let email = new_email();
+        let user = create_user(&state, &email, false).await;
+
+        let conn = &mut state.db_pool.get().await.unwrap();
+        send_verification(&state, conn, &user, &email)
+            .await
+            .unwrap();
+        let token = last_token(&mailer, &email);
+
+        assert_eq!(verify(&state, &token).await, StatusCode::NO_CONTENT);
+        assert!(reload(&state, &user).await.email_verified_at.is_some());
+        assert_eq!(verify(&state, &token).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn resending_replaces_the_link() {
+        let (state, mailer) = setup().await;
+        let email = new_email();
+        let user = create_user(&state, &email, false).await;
+
+        let resend =
+            || async { status(resend_verification(State(state.clone()), auth(&user)).await) };
+        assert_eq!(resend().await, StatusCode::ACCEPTED);
+        let old_token = last_token(&mailer, &email);
+        assert_eq!(resend().await, StatusCode::ACCEPTED);
+        let new_token = last_token(&mailer, &email);
+
+        assert_eq!(verify(&state, &old_token).await, StatusCode::BAD_REQUEST);
+        assert_eq!(verify(&state, &new_token).await, StatusCode::NO_CONTENT);
+        assert_eq!(resend().await, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn changing_email_needs_the_password() {
+        let (state, mailer) = setup().await;
+        let old_email = new_email();
+        let new_email = new_email();
+        let user = create_user(&state, &old_email, true).await;
+
+        let changed = change(&state, &user, "wrong password", &new_email).await;
+        assert_eq!(changed, StatusCode::FORBIDDEN);
+        assert!(mailer.sent_to(&new_email).is_empty());
+
+        let user = reload(&state, &user).await;
+        assert_eq!(user.email.as_deref(), Some(old_email.as_str()));
+        assert!(user.email_verified_at.is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn changed_email_is_verified_again() {
+        let (state, mailer) = setup().await;
+        let old_email = new_email();
+        let new_email = new_email();
+        let user = create_user(&state, &old_email, true).await;
+
+        let changed = change(&state, &user, PASSWORD, &new_email.to_uppercase()).await;
+        assert_eq!(changed, StatusCode::ACCEPTED);
+        let changed = reload(&state, &user).await;
+        assert_eq!(changed.email.as_deref(), Some(new_email.as_str()));
+        assert!(changed.email_verified_at.is_none());
+        assert!(mailer.sent_to(&old_email).is_empty());
+
+        let token = last_token(&mailer, &new_email);
+        assert_eq!(verify(&state, &token).await, StatusCode::NO_CONTENT);
+        assert!(reload(&state, &user).await.email_verified_at.is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn links_to_the_old_email_stop_working() {
+        let (state, mailer) = setup().await;
+        let old_email = new_email();
+        let new_email = new_email();
+        let user = create_user(&state, &old_email, false).await;
+
+        let conn = &mut state.db_pool.get().await.unwrap();
+        send_verification(&state, conn, &user, &old_email)
+            .await
+            .unwrap();
+        let old_token = last_token(&mailer, &old_email);
+
+        let changed = change(&state, &user, PASSWORD, &new_email).await;
+        assert_eq!(changed, StatusCode::ACCEPTED);
+        assert_eq!(verify(&state, &old_token).await, StatusCode::BAD_REQUEST);
+        assert!(reload(&state, &user).await.email_verified_at.is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn taken_username_and_email_are_field_errors() {
+        let (state, _) = setup().await;
+        let email = new_email();
+        let user = create_user(&state, &email, false).await;
+
+        let registered = create_account(
+            &state,
+            RegisterRequest {
+                username: user.username.to_uppercase(),
+                password: PASSWORD.to_string(),
+                email: Some(email.to_uppercase()),
+            },
+        )
+        .await;
+        let Err(AppError::ValidationError(errors)) = registered else {
+            panic!("expected validation errors");
+        };
+        assert_eq!(
+            serde_json::to_value(errors).unwrap(),
+            serde_json::json!({
+                "email": ["is already in use"],
+                "username": ["has been taken"],
+            })
+        );
+
+        let other = create_user(&state, &new_email(), false).await;
+        let changed = change(&state, &other, PASSWORD, &email).await;
+        assert_eq!(changed, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn setting_the_same_email_is_a_conflict() {
+        let (state, mailer) = setup().await;
+        let email = new_email();
+        let user = create_user(&state, &email, true).await;
+
+        let changed = change(&state, &user, PASSWORD, &email).await;
+        assert_eq!(changed, StatusCode::CONFLICT);
+        assert!(mailer.sent_to(&email).is_empty());
+        assert!(reload(&state, &user).await.email_verified_at.is_some());
+    }
 }
@@ -1,30 +1,36 @@
 use std::collections::HashMap;
 
 use axum::{
-    extract::State,
+    extract::{Path, Query, State},
     http::StatusCode,
     routing::{get, post},
     Json, Router,
 };
 use bitvec::prelude::*;
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use lazy_static::lazy_static;
-use rand::thread_rng;
+use rand::{thread_rng, SeedableRng};
 use rand::{seq::SliceRandom, Rng};
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    api::{
+        pick_message, record_solve, seeded_message, signed_text, Avoid, NewRequest, PuzzleType,
+        SeedRequest, Solved,
+    },
+    attempts,
     auth::Auth,
     error::{AppError, AppResult},
-    exp::ExpSource,
-    models::User,
-    util::{generate_sig, get_timestamp, random, verify_solution},
-    AppState, api::{NewSolve, PuzzleType},
+    exp::{self, ExpSource},
+    models::Message,
+    scoring,
+    util::{generate_sig, get_timestamp, key_rng, verify_sig},
+    AppState,
 };
-use anyhow::anyhow;
 
-use super::{profile::ProfileResponse, SubmitResponse};
+use super::SubmitResponse;
 
 lazy_static! {
     static ref BACONIAN: HashMap<char, BitArray<[u8; 1], Msb0>> = HashMap::from([
@@ -63,10 +69,9 @@ lazy_static! {
     ];
 }
 
-fn random_variant() -> [Vec<char>; 2] {
-    let mut rng = thread_rng();
+fn random_variant(rng: &mut impl Rng) -> [Vec<char>; 2] {
     let mut variant = VARIANTS
-        .choose(&mut rng)
+        .choose(rng)
         .expect("needs at least 1 variant")
         .clone();
 
@@ -77,8 +82,7 @@ fn random_variant() -> [Vec<char>; 2] {
     variant
 }
 
-fn encode(variant: &[Vec<char>; 2], c: char) -> Option<String> {
-    let mut rng = thread_rng();
+fn encode(rng: &mut impl Rng, variant: &[Vec<char>; 2], c: char) -> Option<String> {
     let Some(encoding) = BACONIAN.get(&c) else {
         return None;
     };
@@ -88,7 +92,7 @@ fn encode(variant: &[Vec<char>; 2], c: char) -> Option<String> {
         let bit = encoding[i];
         buf.push(
             *variant[bit as usize]
-                .choose(&mut rng)
+                .choose(rng)
                 .expect("parts of variant should have at least 1 option"),
         );
     }
@@ -96,53 +100,129 @@ fn encode(variant: &[Vec<char>; 2], c: char) -> Option<String> {
     Some(buf)
 }
 
+/// Encrypts a message with a variant and letter choices drawn from `rng`, one
+/// group of 5 per letter.
+pub fn encrypt(rng: &mut impl Rng, message: &str) -> Vec<String> {
+    let variant = random_variant(rng);
+
+    message
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| encode(rng, &variant, c))
+        .collect()
+}
+
 #[derive(Serialize)]
-struct NewResponse {
+pub struct NewResponse {
     id: i32,
     ciphertext: Vec<String>,
     sig: String,
     timestamp: u128,
     attribution: String,
+    difficulty: Option<f32>,
+    /// Chooses the key. `/seed/:seed?id=` with this and `id` serves the same
+    /// puzzle again
+    seed: u32,
 }
 
-async fn new(State(state): State<AppState>, auth: Option<Auth>) -> AppResult<Json<NewResponse>> {
-    use crate::schema::messages;
+/// Serves a random puzzle, avoiding the ones the user has solved or was
+/// served recently until there are no others left.
+pub async fn new(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Query(req): Query<NewRequest>,
+) -> AppResult<Json<NewResponse>> {
     let conn = &mut state.db_pool.get().await?;
+    let recent = match &auth {
+        Some(Auth(claims)) => {
+            attempts::recent_messages(conn, &claims.uid, PuzzleType::Baconian).await?
+        }
+        None => vec![],
+    };
+    let avoid = Avoid {
+        solved_by: auth.as_ref().map(|Auth(claims)| claims.uid.as_str()),
+        messages: &recent,
+    };
 
-    let Some((msg_id, message, attribution)) = messages::table
-        .select((messages::id, messages::message, messages::attribution))
-        .order(random())
-        .first::<(i32, String, Option<String>)>(conn)
-        .await
-        .optional()? else {
-            return Err(anyhow!("expected 1 message in database").into())
-        };
+    // What gets picked depends on what the user has seen, so it is replayed
+    // by its id rather than the seed
+    let message = pick_message(
+        conn,
+        &mut ChaCha8Rng::from_entropy(),
+        PuzzleType::Baconian,
+        &auth,
+        &req,
+        avoid,
+    )
+    .await?;
+    let seed = thread_rng().gen();
 
-    let variant = random_variant();
+    Ok(Json(serve(&state, conn, &auth, message, seed).await?))
+}
 
-    let ciphertext: Vec<String> = message
-        .to_lowercase()
-        .chars()
-        .filter_map(|c| encode(&variant, c))
-        .collect();
+/// Regenerates the puzzle `new` served with this seed, given its `id`. Without
+/// an id, the seed picks the message from the whole pool, so everyone with the
+/// same seed and filters gets the same puzzle.
+pub async fn seeded(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Path(seed): Path<u32>,
+    Query(seed_req): Query<SeedRequest>,
+    Query(req): Query<NewRequest>,
+) -> AppResult<Json<NewResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let message = seeded_message(conn, seed, PuzzleType::Baconian, &auth, &seed_req, &req).await?;
+
+    Ok(Json(serve(&state, conn, &auth, message, seed).await?))
+}
+
+/// Encrypts `message` with the key generated from `seed` and signs it for the
+/// user.
+pub async fn serve(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    auth: &Option<Auth>,
+    message: Message,
+    seed: u32,
+) -> AppResult<NewResponse> {
+    let Message {
+        id: msg_id,
+        message,
+        attribution,
+        baconian_difficulty: difficulty,
+        ..
+    } = message;
+
+    // The key gets its own rng so it only depends on the seed, not on how many
+    // messages there were to pick from
+    let ciphertext = encrypt(&mut key_rng(seed), &message);
 
     let timestamp = get_timestamp();
+    if let Some(Auth(claims)) = auth {
+        attempts::start(conn, &claims.uid, PuzzleType::Baconian, msg_id, timestamp).await?;
+    }
 
+    let letters = message
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .collect::<String>();
     let sig = generate_sig(
         &state.hmac_key,
-        &auth,
+        auth,
         msg_id,
         timestamp,
-        message.chars().filter(|c| c.is_alphabetic()).collect(),
+        signed_text(seed, &letters),
     );
 
-    Ok(Json(NewResponse {
+    Ok(NewResponse {
         id: msg_id,
         ciphertext,
         sig,
         timestamp,
         attribution: attribution.unwrap_or("Unknown".to_string()),
-    }))
+        difficulty,
+        seed,
+    })
 }
 
 #[derive(Deserialize)]
@@ -151,6 +231,9 @@ struct SubmitRequest {
     message: String,
     sig: String,
     timestamp: u128,
+    seed: u32,
+    /// The code of the shared link the puzzle came from, if any
+    share: Option<String>,
 }
 
 async fn submit(
@@ -158,20 +241,43 @@ async fn submit(
     auth: Option<Auth>,
     Json(req): Json<SubmitRequest>,
 ) -> AppResult<Json<SubmitResponse>> {
-    use crate::schema::{messages, users, solves};
+    use crate::schema::messages;
     let conn = &mut state.db_pool.get().await?;
 
-    if verify_solution(
+    let Some((plaintext, difficulty)) = messages::table
+        .select((messages::message, messages::baconian_difficulty))
+        .filter(messages::id.eq(req.id))
+        .first::<(String, Option<f32>)>(conn)
+        .await
+        .optional()? else {
+            return Err(AppError::from(StatusCode::BAD_REQUEST, "invalid puzzle"));
+        };
+
+    let letters = plaintext
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .collect::<String>();
+    if !verify_sig(
         &state.hmac_key,
         &auth,
         req.id,
         req.timestamp,
-        req.message.chars().filter(|c| c.is_alphabetic()).collect(),
+        signed_text(req.seed, &letters),
         req.sig,
     )? {
+        return Err(AppError::from(StatusCode::BAD_REQUEST, "invalid puzzle"));
+    }
+
+    let grade = scoring::grade(
+        PuzzleType::Baconian,
+        &plaintext,
+        &req.message,
+        scoring::points(PuzzleType::Baconian),
+    );
+    if grade.score > 0 {
         let time_taken = get_timestamp() - req.timestamp;
         if let Some(Auth(claims)) = auth {
-            let solve_exp = 75;
+            let solve_exp = exp::scale_by_difficulty(75, difficulty);
             let time_taken_sec = (time_taken as f64) / 1000.0;
             let time_bonus =
                 (100_f64 - ((time_taken_sec - 10.0).max(0.0) * 2.5 / 3.0)).max(0.0) as i32;
@@ -183,45 +289,29 @@ async fn submit(
                 exp_sources.push(ExpSource::additive("Time Bonus", time_bonus));
             }
 
-            let user = diesel::update(users::table)
-                .filter(users::id.eq(claims.uid))
-                .set((
-                    users::experience.eq(users::experience + sum),
-                    users::solved.eq(users::solved + 1),
-                ))
-                .get_result::<User>(conn)
-                .await?;
-
-            diesel::insert_into(solves::table)
-                .values(NewSolve::new(
-                    PuzzleType::Baconian,
-                    req.id,
-                    &user,
-                    time_taken as i32,
+            return Ok(Json(
+                record_solve(
+                    conn,
+                    &claims.uid,
+                    Solved {
+                        puzzle_type: PuzzleType::Baconian,
+                        message_id: req.id,
+                        seed: req.seed,
+                        share: req.share,
+                        timestamp: req.timestamp,
+                        time_taken,
+                        grade,
+                    },
+                    exp_sources,
                     sum,
-                ))
-                .execute(conn)
-                .await?;
-
-            return Ok(Json(SubmitResponse {
-                plaintext: messages::table
-                    .select(messages::message)
-                    .filter(messages::id.eq(req.id))
-                    .first::<String>(conn)
-                    .await?,
-                time_taken,
-                profile: Some(ProfileResponse::from(user)),
-                exp_sources: Some(exp_sources),
-                total_exp: Some(sum),
-            }));
+                )
+                .await?,
+            ));
         } else {
             return Ok(Json(SubmitResponse {
-                plaintext: messages::table
-                    .select(messages::message)
-                    .filter(messages::id.eq(req.id))
-                    .first::<String>(conn)
-                    .await?,
+                plaintext,
                 time_taken,
+                grade,
                 profile: None,
                 exp_sources: None,
                 total_exp: None,
@@ -229,6 +319,17 @@ async fn submit(
         }
     }
 
+    if let Some(Auth(claims)) = &auth {
+        attempts::record_wrong(
+            conn,
+            &claims.uid,
+            PuzzleType::Baconian,
+            req.id,
+            req.timestamp,
+        )
+        .await?;
+    }
+
     Err(AppError::from(
         StatusCode::EXPECTATION_FAILED,
         "The puzzle is incorrect",
@@ -238,5 +339,6 @@ async fn submit(
 pub fn app() -> Router<AppState> {
     Router::new()
         .route("/new", get(new))
+        .route("/seed/:seed", get(seeded))
         .route("/submit", post(submit))
 }
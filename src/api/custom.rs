@@ -0,0 +1,377 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Local};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Auth,
+    error::{AppError, AppResult},
+    models::CustomPuzzle,
+    schema::{custom_puzzles, custom_solves, users},
+    scoring::{self, Grade},
+    util::{custom_key_rng, generate_sig, get_timestamp, verify_sig},
+    AppState,
+};
+
+use super::{Ciphertext, PuzzleType};
+
+const CODE_LENGTH: usize = 10;
+const MAX_PLAINTEXT_LENGTH: usize = 255;
+const MAX_ATTRIBUTION_LENGTH: usize = 127;
+
+/// Who can open a custom puzzle. Neither is ever served from `new`.
+#[repr(i16)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Visibility {
+    /// Only the author
+    Private = 0,
+    /// Anyone with the link
+    Unlisted = 1,
+}
+
+impl TryFrom<i16> for Visibility {
+    type Error = AppError;
+
+    fn try_from(v: i16) -> Result<Self, Self::Error> {
+        match v {
+            x if x == Visibility::Private as i16 => Ok(Visibility::Private),
+            x if x == Visibility::Unlisted as i16 => Ok(Visibility::Unlisted),
+            _ => Err(AppError::InternalServerError(anyhow!("invalid Visibility"))),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateRequest {
+    puzzle_type: PuzzleType,
+    plaintext: String,
+    attribution: Option<String>,
+    visibility: Visibility,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CustomPuzzleResponse {
+    code: String,
+    puzzle_type: PuzzleType,
+    ciphertext: Ciphertext,
+    attribution: String,
+    author: String,
+    visibility: Visibility,
+    created_at: String,
+    sig: String,
+    timestamp: u128,
+    /// Only sent to the author
+    plaintext: Option<String>,
+}
+
+/// The code is signed with the plaintext so a signature can't be replayed on
+/// another puzzle.
+fn signed_text(puzzle: &CustomPuzzle) -> String {
+    format!("{}:{}", puzzle.code, puzzle.plaintext)
+}
+
+impl CustomPuzzleResponse {
+    fn new(
+        state: &AppState,
+        auth: &Option<Auth>,
+        puzzle: CustomPuzzle,
+        author: String,
+    ) -> AppResult<Self> {
+        let puzzle_type = PuzzleType::try_from(puzzle.puzzle_type)?;
+        let is_author = matches!(auth, Some(Auth(claims)) if claims.uid == puzzle.author);
+        let timestamp = get_timestamp();
+
+        Ok(Self {
+            ciphertext: Ciphertext::encrypt(
+                puzzle_type,
+                &mut custom_key_rng(&puzzle.code),
+                &puzzle.plaintext,
+            ),
+            sig: generate_sig(&state.hmac_key, auth, 0, timestamp, signed_text(&puzzle)),
+            code: puzzle.code,
+            puzzle_type,
+            attribution: puzzle.attribution.unwrap_or("Unknown".to_string()),
+            author,
+            visibility: Visibility::try_from(puzzle.visibility)?,
+            created_at: format!("{}", puzzle.created_at.format("%F %I:%M %P")),
+            timestamp,
+            plaintext: is_author.then_some(puzzle.plaintext),
+        })
+    }
+}
+
+/// Creates a puzzle from the user's own plaintext. It is kept out of the
+/// message pool and its solves don't count towards exp or ratings.
+async fn create(
+    State(state): State<AppState>,
+    auth: Auth,
+    Json(req): Json<CreateRequest>,
+) -> AppResult<Json<CustomPuzzleResponse>> {
+    let plaintext = req.plaintext.trim();
+    if !plaintext.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "plaintext must have at least 1 letter",
+        ));
+    }
+    if plaintext.chars().count() > MAX_PLAINTEXT_LENGTH {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            format!("plaintext can be at most {MAX_PLAINTEXT_LENGTH} characters"),
+        ));
+    }
+    let attribution = req
+        .attribution
+        .as_deref()
+        .map(str::trim)
+        .filter(|a| !a.is_empty());
+    if attribution.map_or(0, |a| a.chars().count()) > MAX_ATTRIBUTION_LENGTH {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            format!("attribution can be at most {MAX_ATTRIBUTION_LENGTH} characters"),
+        ));
+    }
+
+    let conn = &mut state.db_pool.get().await?;
+
+    let puzzle = diesel::insert_into(custom_puzzles::table)
+        .values((
+            custom_puzzles::code.eq(nanoid!(CODE_LENGTH)),
+            custom_puzzles::author.eq(&auth.0.uid),
+            custom_puzzles::puzzle_type.eq(req.puzzle_type as i16),
+            custom_puzzles::plaintext.eq(plaintext),
+            custom_puzzles::attribution.eq(attribution),
+            custom_puzzles::visibility.eq(req.visibility as i16),
+        ))
+        .get_result::<CustomPuzzle>(conn)
+        .await?;
+
+    let author = auth.0.username.clone();
+    Ok(Json(CustomPuzzleResponse::new(
+        &state,
+        &Some(auth),
+        puzzle,
+        author,
+    )?))
+}
+
+/// The puzzles the user has written, newest first.
+async fn mine(
+    State(state): State<AppState>,
+    auth: Auth,
+) -> AppResult<Json<Vec<CustomPuzzleResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let puzzles = custom_puzzles::table
+        .filter(custom_puzzles::author.eq(&auth.0.uid))
+        .order(custom_puzzles::created_at.desc())
+        .load::<CustomPuzzle>(conn)
+        .await?;
+
+    let author = auth.0.username.clone();
+    let auth = Some(auth);
+    Ok(Json(
+        puzzles
+            .into_iter()
+            .map(|puzzle| CustomPuzzleResponse::new(&state, &auth, puzzle, author.clone()))
+            .collect::<AppResult<_>>()?,
+    ))
+}
+
+/// Loads a custom puzzle and its author's username. Private puzzles are only
+/// found for their author.
+async fn find(
+    conn: &mut AsyncPgConnection,
+    auth: &Option<Auth>,
+    code: &str,
+) -> AppResult<(CustomPuzzle, String)> {
+    let Some((puzzle, author)) = custom_puzzles::table
+        .inner_join(users::table)
+        .select((custom_puzzles::all_columns, users::username))
+        .filter(custom_puzzles::code.eq(code))
+        .first::<(CustomPuzzle, String)>(conn)
+        .await
+        .optional()? else {
+            return Err(AppError::from(StatusCode::NOT_FOUND, "Puzzle not found"));
+        };
+
+    let is_author = matches!(auth, Some(Auth(claims)) if claims.uid == puzzle.author);
+    if puzzle.visibility == Visibility::Private as i16 && !is_author {
+        return Err(AppError::from(StatusCode::NOT_FOUND, "Puzzle not found"));
+    }
+
+    Ok((puzzle, author))
+}
+
+async fn puzzle(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Path(code): Path<String>,
+) -> AppResult<Json<CustomPuzzleResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let (puzzle, author) = find(conn, &auth, &code).await?;
+    Ok(Json(CustomPuzzleResponse::new(
+        &state, &auth, puzzle, author,
+    )?))
+}
+
+async fn delete(
+    State(state): State<AppState>,
+    auth: Auth,
+    Path(code): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let deleted = diesel::delete(custom_puzzles::table)
+        .filter(custom_puzzles::code.eq(code))
+        .filter(custom_puzzles::author.eq(&auth.0.uid))
+        .execute(conn)
+        .await?;
+
+    if deleted == 0 {
+        return Err(AppError::from(StatusCode::NOT_FOUND, "Puzzle not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct SubmitRequest {
+    message: String,
+    sig: String,
+    timestamp: u128,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CustomSubmitResponse {
+    plaintext: String,
+    time_taken: u128,
+    #[serde(flatten)]
+    grade: Grade,
+}
+
+/// Grades a custom puzzle. Solves are kept with the puzzle rather than with
+/// ranked solves, so they never earn exp or move ratings.
+async fn submit(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Path(code): Path<String>,
+    Json(req): Json<SubmitRequest>,
+) -> AppResult<Json<CustomSubmitResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let (puzzle, _) = find(conn, &auth, &code).await?;
+
+    if !verify_sig(
+        &state.hmac_key,
+        &auth,
+        0,
+        req.timestamp,
+        signed_text(&puzzle),
+        req.sig,
+    )? {
+        return Err(AppError::from(StatusCode::BAD_REQUEST, "invalid puzzle"));
+    }
+
+    let puzzle_type = PuzzleType::try_from(puzzle.puzzle_type)?;
+    let grade = scoring::grade(
+        puzzle_type,
+        &puzzle.plaintext,
+        &req.message,
+        scoring::points(puzzle_type),
+    );
+    if grade.score == 0 {
+        return Err(AppError::from(
+            StatusCode::EXPECTATION_FAILED,
+            "The puzzle is incorrect",
+        ));
+    }
+
+    let time_taken = get_timestamp() - req.timestamp;
+    if let Some(Auth(claims)) = &auth {
+        diesel::insert_into(custom_solves::table)
+            .values((
+                custom_solves::code.eq(&puzzle.code),
+                custom_solves::user_id.eq(&claims.uid),
+                custom_solves::time_taken.eq(time_taken as i32),
+                custom_solves::errors.eq(grade.errors as i32),
+                custom_solves::score.eq(grade.score),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+    }
+
+    Ok(Json(CustomSubmitResponse {
+        plaintext: puzzle.plaintext,
+        time_taken,
+        grade,
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CustomSolveResponse {
+    solver: String,
+    solved_at: String,
+    time_taken: i32,
+    errors: i32,
+    score: i32,
+}
+
+/// Everyone's solve of a custom puzzle, best first.
+async fn solves(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Path(code): Path<String>,
+) -> AppResult<Json<Vec<CustomSolveResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+    let (puzzle, _) = find(conn, &auth, &code).await?;
+
+    let solves = custom_solves::table
+        .inner_join(users::table)
+        .select((
+            users::username,
+            custom_solves::solved_at,
+            custom_solves::time_taken,
+            custom_solves::errors,
+            custom_solves::score,
+        ))
+        .filter(custom_solves::code.eq(&puzzle.code))
+        .order((custom_solves::score.desc(), custom_solves::time_taken))
+        .load::<(String, DateTime<Local>, i32, i32, i32)>(conn)
+        .await?;
+
+    Ok(Json(
+        solves
+            .into_iter()
+            .map(
+                |(solver, solved_at, time_taken, errors, score)| CustomSolveResponse {
+                    solver,
+                    solved_at: format!("{}", solved_at.format("%F %I:%M %P")),
+                    time_taken,
+                    errors,
+                    score,
+                },
+            )
+            .collect(),
+    ))
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/", get(mine).post(create))
+        .route("/:code", get(puzzle).delete(delete))
+        .route("/:code/submit", post(submit))
+        .route("/:code/solves", get(solves))
+}
@@ -1,45 +1,354 @@
 use anyhow::anyhow;
-use axum::Router;
-use diesel::Insertable;
-use serde::Serialize;
+use axum::{http::StatusCode, Router};
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    sql_types::{Bool, Float4},
+};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::FutureExt;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::{error::AppError, exp::ExpSource, models::User, schema, AppState};
+use crate::{
+    achievements, attempts,
+    auth::Auth,
+    error::{AppError, AppResult},
+    exp::{self, ExpSource},
+    models::{Message, User},
+    rating,
+    schema::{self, message_ratings, message_tags, messages, users},
+    scoring::Grade,
+    streak,
+    AppState,
+};
 use std::convert::TryFrom;
 
 use self::profile::ProfileResponse;
 
+pub mod admin;
 pub mod aristocrat;
 pub mod auth;
 pub mod baconian;
+pub mod custom;
+pub mod practice;
 pub mod profile;
+pub mod puzzles;
 pub mod solves;
+pub mod suggestions;
+pub mod tags;
+pub mod tests;
 
 pub fn app() -> Router<AppState> {
     Router::new()
+        .nest("/admin", admin::app())
         .nest("/aristocrat", aristocrat::app())
         .nest("/baconian", baconian::app())
+        .nest("/custom", custom::app())
+        .nest("/messages", suggestions::app())
+        .nest("/practice", practice::app())
         .nest("/profile", profile::app())
+        .nest("/puzzles", puzzles::app())
         .nest("/solves", solves::app())
+        .nest("/tags", tags::app())
+        .nest("/tests", tests::app())
         .nest("/auth", auth::app())
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewRequest {
+    min_difficulty: Option<f32>,
+    max_difficulty: Option<f32>,
+    /// Prefer puzzles rated close to the user's own rating
+    #[serde(default)]
+    near_rating: bool,
+    /// Only messages with this tag
+    tag: Option<String>,
+}
+
+/// How far from the user's rating to look for a puzzle before widening the
+/// search, and finally giving up on the rating altogether.
+const RATING_WINDOWS: [Option<f32>; 4] = [Some(100.0), Some(200.0), Some(400.0), None];
+
+/// Random ids `pick_message` tries before falling back to counting the
+/// matches.
+const PICK_PROBES: usize = 8;
+
+/// Messages `pick_message` should only pick when nothing else matches.
+#[derive(Default, Clone, Copy)]
+pub struct Avoid<'a> {
+    /// Messages this user has solved as the type of puzzle being picked
+    pub solved_by: Option<&'a str>,
+    /// Particular messages, like the ones the user was served recently
+    pub messages: &'a [i32],
+}
+
+impl<'a> Avoid<'a> {
+    fn is_empty(&self) -> bool {
+        self.solved_by.is_none() && self.messages.is_empty()
+    }
+
+    /// What to avoid at first, then with only solved messages avoided, then
+    /// with nothing avoided.
+    fn fallbacks(self) -> Vec<Avoid<'a>> {
+        let mut fallbacks = vec![self];
+        if self.solved_by.is_some() && !self.messages.is_empty() {
+            fallbacks.push(Avoid {
+                messages: &[],
+                ..self
+            });
+        }
+        if !self.is_empty() {
+            fallbacks.push(Avoid::default());
+        }
+        fallbacks
+    }
+}
+
+/// Picks a message from the pool that matches the request's filters using
+/// `rng`, so the same seed picks the same message as long as the pool and the
+/// user's rating stay the same. Messages in `avoid` are only picked once the
+/// rest of the matching pool has run out.
+///
+/// Every matching message is equally likely. It first tries a few random ids
+/// between the lowest and highest, which only reads a few rows of the id index
+/// however large the pool is. When the filters match too little of the pool
+/// for that to land, it counts the matches and picks one at a random offset.
+pub async fn pick_message(
+    conn: &mut AsyncPgConnection,
+    rng: &mut (impl Rng + Send),
+    puzzle_type: PuzzleType,
+    auth: &Option<Auth>,
+    req: &NewRequest,
+    avoid: Avoid<'_>,
+) -> AppResult<Message> {
+    if matches!((req.min_difficulty, req.max_difficulty), (Some(min), Some(max)) if min > max) {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "minDifficulty can't be more than maxDifficulty",
+        ));
+    }
+
+    let user_rating = match auth {
+        Some(Auth(claims)) if req.near_rating => {
+            Some(rating::user_rating(conn, &claims.uid, puzzle_type).await?)
+        }
+        _ => None,
+    };
+    let windows = if user_rating.is_some() {
+        &RATING_WINDOWS[..]
+    } else {
+        &RATING_WINDOWS[RATING_WINDOWS.len() - 1..]
+    };
+
+    let (Some(first_id), Some(last_id)) = messages::table
+        .select((
+            diesel::dsl::min(messages::id),
+            diesel::dsl::max(messages::id),
+        ))
+        .get_result::<(Option<i32>, Option<i32>)>(conn)
+        .await? else {
+            return Err(no_messages(req));
+        };
+
+    // Widen the rating window before giving up on avoiding messages
+    let searches = avoid
+        .fallbacks()
+        .into_iter()
+        .flat_map(|avoid| windows.iter().map(move |&window| (avoid, window)))
+        .collect::<Vec<_>>();
+    for (avoid, window) in searches {
+        let filtered = || {
+            let mut query = messages::table
+                .left_join(
+                    message_ratings::table.on(message_ratings::message_id
+                        .eq(messages::id)
+                        .and(message_ratings::puzzle_type.eq(puzzle_type as i16))),
+                )
+                .select(messages::id)
+                .filter(messages::deleted_at.is_null())
+                .into_boxed();
+            if let Some(min) = req.min_difficulty {
+                query = match puzzle_type {
+                    PuzzleType::Aristocrat => query.filter(messages::difficulty.ge(min)),
+                    PuzzleType::Baconian => query.filter(messages::baconian_difficulty.ge(min)),
+                };
+            }
+            if let Some(max) = req.max_difficulty {
+                query = match puzzle_type {
+                    PuzzleType::Aristocrat => query.filter(messages::difficulty.le(max)),
+                    PuzzleType::Baconian => query.filter(messages::baconian_difficulty.le(max)),
+                };
+            }
+            if let Some(tag) = &req.tag {
+                query = query.filter(
+                    messages::id.eq_any(
+                        message_tags::table
+                            .inner_join(schema::tags::table)
+                            .filter(schema::tags::name.eq(tag.to_lowercase()))
+                            .select(message_tags::message_id),
+                    ),
+                );
+            }
+            if let (Some(user_rating), Some(window)) = (user_rating, window) {
+                query = query.filter(
+                    sql::<Bool>(&format!("{} BETWEEN ", rating::puzzle_rating_sql(puzzle_type)))
+                        .bind::<Float4, _>(user_rating - window)
+                        .sql(" AND ")
+                        .bind::<Float4, _>(user_rating + window),
+                );
+            }
+            if let Some(uid) = avoid.solved_by {
+                query = query.filter(
+                    messages::id.ne_all(
+                        schema::solves::table
+                            .select(schema::solves::message_id)
+                            .filter(schema::solves::solver.eq(uid))
+                            .filter(schema::solves::puzzle_type.eq(puzzle_type as i16)),
+                    ),
+                );
+            }
+            if !avoid.messages.is_empty() {
+                query = query.filter(messages::id.ne_all(avoid.messages));
+            }
+            query
+        };
+
+        let mut id = None;
+        for _ in 0..PICK_PROBES {
+            let probe = rng.gen_range(first_id..=last_id);
+            id = filtered()
+                .filter(messages::id.eq(probe))
+                .first::<i32>(conn)
+                .await
+                .optional()?;
+            if id.is_some() {
+                break;
+            }
+        }
+        if id.is_none() {
+            let count = filtered().count().get_result::<i64>(conn).await?;
+            if count > 0 {
+                id = Some(
+                    filtered()
+                        .order(messages::id)
+                        .offset(rng.gen_range(0..count))
+                        .first::<i32>(conn)
+                        .await?,
+                );
+            }
+        }
+        if let Some(id) = id {
+            return Ok(messages::table.find(id).first::<Message>(conn).await?);
+        }
+    }
+
+    Err(no_messages(req))
+}
+
+/// What a served puzzle is signed with besides the user, id and time. The seed
+/// is included so a solve can't pass for another key of the same message.
+pub fn signed_text(seed: u32, text: &str) -> String {
+    format!("{seed}:{text}")
+}
+
+#[derive(Deserialize)]
+pub struct SeedRequest {
+    /// The message `new` served with the seed. Left out, the seed picks one.
+    id: Option<i32>,
+}
+
+/// The message for a seeded puzzle: the one `new` served, given its id, or
+/// else the one the seed picks from the whole pool.
+pub async fn seeded_message(
+    conn: &mut AsyncPgConnection,
+    seed: u32,
+    puzzle_type: PuzzleType,
+    auth: &Option<Auth>,
+    seed_req: &SeedRequest,
+    req: &NewRequest,
+) -> AppResult<Message> {
+    match seed_req.id {
+        Some(id) => messages::table
+            .find(id)
+            .filter(messages::deleted_at.is_null())
+            .first::<Message>(conn)
+            .await
+            .optional()?
+            .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "Puzzle not found")),
+        None => {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed as u64);
+            pick_message(conn, &mut rng, puzzle_type, auth, req, Avoid::default()).await
+        }
+    }
+}
+
+fn no_messages(req: &NewRequest) -> AppError {
+    if req.tag.is_some() {
+        AppError::from(
+            StatusCode::NOT_FOUND,
+            "no messages match the requested tag and difficulty",
+        )
+    } else if req.min_difficulty.is_some() || req.max_difficulty.is_some() {
+        AppError::from(
+            StatusCode::NOT_FOUND,
+            "no messages match the requested difficulty",
+        )
+    } else {
+        anyhow!("expected 1 message in database").into()
+    }
+}
+
+/// Ciphertext of any type. Baconian is sent as its groups of 5.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Ciphertext {
+    Aristocrat(String),
+    Baconian(Vec<String>),
+}
+
+impl Ciphertext {
+    pub fn encrypt(puzzle_type: PuzzleType, rng: &mut impl Rng, message: &str) -> Self {
+        match puzzle_type {
+            PuzzleType::Aristocrat => Ciphertext::Aristocrat(aristocrat::encrypt(rng, message)),
+            PuzzleType::Baconian => Ciphertext::Baconian(baconian::encrypt(rng, message)),
+        }
+    }
+}
+
+/// A puzzle of any type, as `new` serves it.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Puzzle {
+    Aristocrat(aristocrat::NewResponse),
+    Baconian(baconian::NewResponse),
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SubmitResponse {
     plaintext: String,
     time_taken: u128,
+    #[serde(flatten)]
+    grade: Grade,
     profile: Option<ProfileResponse>,
     exp_sources: Option<Vec<ExpSource>>,
     total_exp: Option<i32>,
 }
 
 #[repr(i16)]
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PuzzleType {
     Aristocrat = 0,
     Baconian = 1,
 }
 
+impl PuzzleType {
+    pub const ALL: [PuzzleType; 2] = [PuzzleType::Aristocrat, PuzzleType::Baconian];
+}
+
 impl TryFrom<i16> for PuzzleType {
     type Error = AppError;
 
@@ -79,3 +388,148 @@ impl NewSolve {
         }
     }
 }
+
+/// A puzzle the user submitted a correct solution for.
+pub struct Solved {
+    pub puzzle_type: PuzzleType,
+    pub message_id: i32,
+    /// What the key was generated from, as signed
+    pub seed: u32,
+    /// The code of the shared link the puzzle came from, if any
+    pub share: Option<String>,
+    /// When the puzzle was signed, in milliseconds
+    pub timestamp: u128,
+    pub time_taken: u128,
+    pub grade: Grade,
+}
+
+/// Awards the exp for a verified solve on top of `exp_sources`, which total
+/// `sum`: scales it by the partial credit, adds the first try bonus, applies
+/// the streak multiplier, unlocks achievements, then records the solve against
+/// the user and the shared link it came from, and credits whoever suggested the
+/// message. It all happens in one transaction, so a failure partway through
+/// awards and records nothing.
+///
+/// Only the first correct submission for a puzzle the user was served counts,
+/// so a solve can't be submitted again for more exp.
+pub async fn record_solve(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    solved: Solved,
+    exp_sources: Vec<ExpSource>,
+    sum: i32,
+) -> AppResult<SubmitResponse> {
+    let uid = uid.to_string();
+    conn.transaction::<_, AppError, _>(|conn| {
+        async move { award_solve(conn, &uid, solved, exp_sources, sum).await }.boxed()
+    })
+    .await
+}
+
+async fn award_solve(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    solved: Solved,
+    mut exp_sources: Vec<ExpSource>,
+    mut sum: i32,
+) -> AppResult<SubmitResponse> {
+    let Some(wrong_submissions) = attempts::finish(
+        conn,
+        uid,
+        solved.puzzle_type,
+        solved.message_id,
+        solved.timestamp,
+    )
+    .await? else {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "this puzzle was already solved or was never served",
+        ));
+    };
+
+    if let Some(code) = &solved.share {
+        puzzles::record_solve(conn, code, uid, &solved).await?;
+    }
+
+    let Solved {
+        puzzle_type,
+        message_id,
+        time_taken,
+        grade,
+        ..
+    } = solved;
+
+    let user = users::table.find(uid).first::<User>(conn).await?;
+    let streak = streak::streak(conn, &user, true).await?;
+    rating::update(conn, uid, puzzle_type, message_id, time_taken as i32).await?;
+
+    let credit = grade.credit();
+    if credit < 1.0 {
+        sum = (sum as f64 * credit) as i32;
+        exp_sources.push(ExpSource::multiplier(
+            format!("{} Errors", grade.errors),
+            credit,
+        ));
+    }
+
+    if wrong_submissions == 0 {
+        sum += exp::FIRST_TRY_BONUS;
+        exp_sources.push(ExpSource::additive("First Try", exp::FIRST_TRY_BONUS));
+    }
+
+    let multiplier = exp::streak_multiplier(streak.current);
+    if multiplier > 1.0 {
+        sum = (sum as f64 * multiplier) as i32;
+        exp_sources.push(ExpSource::multiplier(
+            format!("{} Day Streak", streak.current),
+            multiplier,
+        ));
+    }
+
+    for achievement in achievements::unlock(
+        conn,
+        uid,
+        puzzle_type,
+        time_taken as i32,
+        streak.current,
+    )
+    .await?
+    {
+        sum += achievement.exp_reward;
+        exp_sources.push(ExpSource::special(achievement.name, achievement.exp_reward));
+    }
+
+    let user = diesel::update(users::table)
+        .filter(users::id.eq(uid))
+        .set((
+            users::experience.eq(users::experience + sum),
+            users::solved.eq(users::solved + 1),
+        ))
+        .get_result::<User>(conn)
+        .await?;
+
+    diesel::insert_into(schema::solves::table)
+        .values(NewSolve::new(
+            puzzle_type,
+            message_id,
+            &user,
+            time_taken as i32,
+            sum,
+        ))
+        .execute(conn)
+        .await?;
+    suggestions::credit_contributor(conn, message_id, uid).await?;
+
+    Ok(SubmitResponse {
+        plaintext: messages::table
+            .select(messages::message)
+            .filter(messages::id.eq(message_id))
+            .first::<String>(conn)
+            .await?,
+        time_taken,
+        grade,
+        profile: Some(ProfileResponse::new(conn, user).await?),
+        exp_sources: Some(exp_sources),
+        total_exp: Some(sum),
+    })
+}
@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Local};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::{
+    attempts,
+    auth::Auth,
+    error::{AppError, AppResult},
+    rating,
+    schema::solves,
+    AppState,
+};
+
+use super::{aristocrat, baconian, NewRequest, Puzzle, PuzzleType};
+
+/// How many of the most recent solves of a type to judge the user by.
+const HISTORY: i64 = 10;
+
+/// How many difficulty points either side of the target to accept.
+const DIFFICULTY_SPREAD: f32 = 1.5;
+
+struct Review {
+    puzzle_type: PuzzleType,
+    /// How many review intervals have passed since the type was last practiced
+    overdue: f64,
+    target_difficulty: f32,
+    reasons: Vec<String>,
+}
+
+/// Schedules a puzzle type for review, SM-2 style: the interval between
+/// reviews grows with how many times the type has been solved, how quickly it
+/// is solved compared to par and how often submissions are correct, so weak
+/// types come back around sooner.
+async fn review(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+) -> AppResult<Review> {
+    let history = solves::table
+        .select((solves::solved_at, solves::time_taken))
+        .filter(solves::solver.eq(uid))
+        .filter(solves::puzzle_type.eq(puzzle_type as i16))
+        .order(solves::solved_at.desc())
+        .limit(HISTORY)
+        .load::<(DateTime<Local>, i32)>(conn)
+        .await?;
+
+    let [(last_solved, _), ..] = history.as_slice() else {
+        return Ok(Review {
+            puzzle_type,
+            overdue: f64::INFINITY,
+            target_difficulty: 3.0,
+            reasons: vec![format!("You haven't tried a {puzzle_type:?} yet")],
+        });
+    };
+
+    let stats = attempts::stats(conn, uid, Some(puzzle_type)).await?;
+    let accuracy = stats.accuracy().unwrap_or(1.0) as f64;
+
+    let par = rating::par_time(puzzle_type) as f64;
+    let average = history.iter().map(|(_, t)| *t as f64).sum::<f64>() / history.len() as f64;
+    let ease = (par / average * accuracy).clamp(0.5, 2.0);
+    let interval_days = ease * (1.0 + (history.len() as f64).ln());
+    let gap_days = (Local::now() - *last_solved).num_minutes() as f64 / (24.0 * 60.0);
+
+    let mut reasons = vec![];
+    if gap_days >= 1.0 {
+        reasons.push(format!(
+            "You last solved a {puzzle_type:?} {} days ago",
+            gap_days.floor()
+        ));
+    }
+    if ease < 1.0 {
+        reasons.push(format!(
+            "Your recent {puzzle_type:?} solves average {:.0}s, slower than the {:.0}s par",
+            average / 1000.0,
+            par / 1000.0
+        ));
+    }
+    if accuracy < 0.8 {
+        reasons.push(format!(
+            "Only {:.0}% of your {puzzle_type:?} submissions were correct",
+            accuracy * 100.0
+        ));
+    }
+    if stats.abandoned > 0 {
+        reasons.push(format!(
+            "You gave up on {} {puzzle_type:?} puzzles",
+            stats.abandoned
+        ));
+    }
+    if reasons.is_empty() {
+        reasons.push(format!("Your {puzzle_type:?} is due for review"));
+    }
+
+    Ok(Review {
+        puzzle_type,
+        overdue: gap_days / interval_days,
+        target_difficulty: (5.5 * ease as f32).clamp(1.0, 10.0),
+        reasons,
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PracticeResponse {
+    puzzle_type: PuzzleType,
+    min_difficulty: f32,
+    max_difficulty: f32,
+    reasons: Vec<String>,
+    puzzle: Puzzle,
+}
+
+async fn new_puzzle(
+    state: &AppState,
+    auth: &Auth,
+    puzzle_type: PuzzleType,
+    req: NewRequest,
+) -> AppResult<Puzzle> {
+    let auth = Some(auth.clone());
+    Ok(match puzzle_type {
+        PuzzleType::Aristocrat => Puzzle::Aristocrat(
+            aristocrat::new(State(state.clone()), auth, Query(req))
+                .await?
+                .0,
+        ),
+        PuzzleType::Baconian => Puzzle::Baconian(
+            baconian::new(State(state.clone()), auth, Query(req))
+                .await?
+                .0,
+        ),
+    })
+}
+
+/// Picks the puzzle type most overdue for review and serves a puzzle of it at
+/// a difficulty suited to how well the user does on that type.
+async fn next(State(state): State<AppState>, auth: Auth) -> AppResult<Json<PracticeResponse>> {
+    let mut reviews = vec![];
+    {
+        let conn = &mut state.db_pool.get().await?;
+        for puzzle_type in PuzzleType::ALL {
+            reviews.push(review(conn, &auth.0.uid, puzzle_type).await?);
+        }
+    }
+
+    let review = reviews
+        .into_iter()
+        .max_by(|a, b| a.overdue.total_cmp(&b.overdue))
+        .expect("there should be at least 1 puzzle type");
+
+    let min_difficulty = (review.target_difficulty - DIFFICULTY_SPREAD).max(1.0);
+    let max_difficulty = (review.target_difficulty + DIFFICULTY_SPREAD).min(10.0);
+    let req = NewRequest {
+        min_difficulty: Some(min_difficulty),
+        max_difficulty: Some(max_difficulty),
+        near_rating: false,
+        tag: None,
+    };
+
+    // Fall back to the user's rating when no rated message is in range
+    let puzzle = match new_puzzle(&state, &auth, review.puzzle_type, req).await {
+        Err(AppError::ResponseStatusError(e)) if e.status() == StatusCode::NOT_FOUND => {
+            let req = NewRequest {
+                min_difficulty: None,
+                max_difficulty: None,
+                near_rating: true,
+                tag: None,
+            };
+            new_puzzle(&state, &auth, review.puzzle_type, req).await?
+        }
+        puzzle => puzzle?,
+    };
+
+    Ok(Json(PracticeResponse {
+        puzzle_type: review.puzzle_type,
+        min_difficulty,
+        max_difficulty,
+        reasons: review.reasons,
+        puzzle,
+    }))
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new().route("/next", get(next))
+}
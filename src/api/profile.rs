@@ -1,19 +1,24 @@
+use std::collections::HashMap;
+
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     routing::get,
     Json, Router,
 };
-use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
-use serde::Serialize;
+use chrono::{DateTime, Local};
+use diesel::{dsl::count_distinct, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    api::PuzzleType,
+    attempts::{self, AttemptStats},
     auth::Auth,
     error::{AppError, AppResult},
-    models::User,
-    schema::users,
-    AppState, exp,
+    models::{Achievement, User},
+    schema::{achievements, messages, puzzle_attempts, ratings, solves, user_achievements, users},
+    streak, AppState, exp,
 };
 
 #[derive(Serialize)]
@@ -26,14 +31,45 @@ pub struct ProfileResponse {
     experience: i32,
     exp_required: i32,
     exp_through: i32,
+    timezone: String,
+    current_streak: i32,
+    longest_streak: i32,
+    ratings: Vec<RatingResponse>,
+    attempts: AttemptStats,
+    accuracy: Option<f32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingResponse {
+    puzzle_type: PuzzleType,
+    rating: i32,
+    solves: i32,
 }
 
-impl From<User> for ProfileResponse {
-    fn from(user: User) -> Self {
+impl ProfileResponse {
+    pub async fn new(conn: &mut AsyncPgConnection, user: User) -> AppResult<Self> {
+        let streak = streak::streak(conn, &user, false).await?;
+        let ratings = ratings::table
+            .select((ratings::puzzle_type, ratings::rating, ratings::solves))
+            .filter(ratings::user_id.eq(&user.id))
+            .order(ratings::puzzle_type)
+            .load::<(i16, f32, i32)>(conn)
+            .await?
+            .into_iter()
+            .map(|(puzzle_type, rating, solves)| {
+                Ok(RatingResponse {
+                    puzzle_type: PuzzleType::try_from(puzzle_type)?,
+                    rating: rating.round() as i32,
+                    solves,
+                })
+            })
+            .collect::<AppResult<_>>()?;
+        let attempts = attempts::stats(conn, &user.id, None).await?;
         let exp_through = exp::exp_through(user.experience);
         let level = exp::exp_to_level(user.experience);
-        let exp_required = 1000;
-        Self {
+        let exp_required = exp::exp_required(user.experience);
+        Ok(Self {
             id: user.id,
             username: user.username,
             solved: user.solved,
@@ -41,7 +77,13 @@ impl From<User> for ProfileResponse {
             experience: user.experience,
             exp_required,
             exp_through,
-        }
+            timezone: user.timezone,
+            current_streak: streak.current,
+            longest_streak: streak.longest,
+            ratings,
+            accuracy: attempts.accuracy(),
+            attempts,
+        })
     }
 }
 
@@ -54,12 +96,45 @@ async fn me(State(state): State<AppState>, auth: Auth) -> AppResult<Json<Profile
         .await
         .optional()?
     {
-        return Ok(Json(ProfileResponse::from(user)));
+        return Ok(Json(ProfileResponse::new(conn, user).await?));
     }
 
     return Err(AppError::from(StatusCode::UNAUTHORIZED, "Token invalid"));
 }
 
+#[derive(Deserialize)]
+struct SettingsRequest {
+    timezone: Option<String>,
+}
+
+async fn update_settings(
+    State(state): State<AppState>,
+    auth: Auth,
+    Json(req): Json<SettingsRequest>,
+) -> AppResult<Json<ProfileResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    if let Some(timezone) = req.timezone {
+        let Some(tz) = streak::parse_timezone(&timezone) else {
+            return Err(AppError::from(StatusCode::BAD_REQUEST, "unknown timezone"));
+        };
+        diesel::update(users::table)
+            .filter(users::id.eq(&auth.0.uid))
+            .set(users::timezone.eq(tz.name()))
+            .execute(conn)
+            .await?;
+    }
+
+    let user = users::table
+        .find(&auth.0.uid)
+        .first::<User>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::UNAUTHORIZED, "Token invalid"))?;
+
+    Ok(Json(ProfileResponse::new(conn, user).await?))
+}
+
 async fn profile(
     State(state): State<AppState>,
     Path(username): Path<String>,
@@ -72,13 +147,145 @@ async fn profile(
         .await
         .optional()?
     {
-        return Ok(Json(ProfileResponse::from(user)));
+        return Ok(Json(ProfileResponse::new(conn, user).await?));
     }
     return Err(AppError::from(StatusCode::NOT_FOUND, "Profile not found"));
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AchievementResponse {
+    name: String,
+    description: String,
+    exp_reward: i32,
+    unlocked_at: Option<String>,
+}
+
+async fn user_achievements(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> AppResult<Json<Vec<AchievementResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let Some(user) = users::table
+        .filter(users::username.eq(username))
+        .first::<User>(conn)
+        .await
+        .optional()? else {
+            return Err(AppError::from(StatusCode::NOT_FOUND, "Profile not found"));
+        };
+
+    let unlocked = user_achievements::table
+        .filter(user_achievements::user_id.eq(user.id))
+        .select((
+            user_achievements::achievement_id,
+            user_achievements::unlocked_at,
+        ))
+        .load::<(i32, DateTime<Local>)>(conn)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let achievements = achievements::table
+        .order(achievements::id)
+        .load::<Achievement>(conn)
+        .await?;
+
+    Ok(Json(
+        achievements
+            .into_iter()
+            .map(|achievement| AchievementResponse {
+                unlocked_at: unlocked
+                    .get(&achievement.id)
+                    .map(|at| format!("{}", at.format("%F %I:%M %P"))),
+                name: achievement.name,
+                description: achievement.description,
+                exp_reward: achievement.exp_reward,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageResponse {
+    puzzle_type: PuzzleType,
+    /// Messages that can be served
+    pool: i64,
+    /// How many of them the user has been served
+    seen: i64,
+    solved: i64,
+    /// Fraction of the pool the user has solved
+    coverage: f32,
+}
+
+/// How much of the message pool the user has been served and solved as each
+/// type of puzzle. Deleted messages aren't counted.
+async fn coverage(
+    State(state): State<AppState>,
+    Auth(claims): Auth,
+) -> AppResult<Json<Vec<CoverageResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let pool = messages::table
+        .filter(messages::deleted_at.is_null())
+        .count()
+        .get_result::<i64>(conn)
+        .await?;
+    let seen = puzzle_attempts::table
+        .inner_join(messages::table)
+        .filter(puzzle_attempts::user_id.eq(&claims.uid))
+        .filter(messages::deleted_at.is_null())
+        .group_by(puzzle_attempts::puzzle_type)
+        .select((
+            puzzle_attempts::puzzle_type,
+            count_distinct(puzzle_attempts::message_id),
+        ))
+        .load::<(i16, i64)>(conn)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let solved = solves::table
+        .inner_join(messages::table)
+        .filter(solves::solver.eq(&claims.uid))
+        .filter(messages::deleted_at.is_null())
+        .group_by(solves::puzzle_type)
+        .select((solves::puzzle_type, count_distinct(solves::message_id)))
+        .load::<(i16, i64)>(conn)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    Ok(Json(
+        PuzzleType::ALL
+            .into_iter()
+            .map(|puzzle_type| {
+                let solved = solved.get(&(puzzle_type as i16)).copied().unwrap_or(0);
+                CoverageResponse {
+                    puzzle_type,
+                    pool,
+                    // Solves from before attempts were recorded count as seen
+                    seen: seen
+                        .get(&(puzzle_type as i16))
+                        .copied()
+                        .unwrap_or(0)
+                        .max(solved),
+                    solved,
+                    coverage: if pool > 0 {
+                        solved as f32 / pool as f32
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect(),
+    ))
+}
+
 pub fn app() -> Router<AppState> {
     Router::new()
-        .route("/", get(me))
+        .route("/", get(me).patch(update_settings))
+        .route("/coverage", get(coverage))
         .route("/:username", get(profile))
+        .route("/:username/achievements", get(user_achievements))
 }
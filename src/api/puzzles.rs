@@ -0,0 +1,247 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Local};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Auth,
+    error::{AppError, AppResult},
+    models::{Message, SharedPuzzle},
+    schema::{messages, shared_puzzles, shared_solves, users},
+    AppState,
+};
+
+use super::{aristocrat, baconian, Puzzle, PuzzleType, Solved};
+
+const CODE_LENGTH: usize = 10;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShareRequest {
+    puzzle_type: PuzzleType,
+    /// The id the puzzle was served with
+    id: i32,
+    seed: u32,
+}
+
+#[derive(Serialize)]
+struct ShareResponse {
+    code: String,
+}
+
+/// Mints a code for a puzzle that was served so it can be sent to others.
+/// Sharing the same puzzle again gives back the same code.
+async fn share(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Json(req): Json<ShareRequest>,
+) -> AppResult<Json<ShareResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    if messages::table
+        .find(req.id)
+        .filter(messages::deleted_at.is_null())
+        .select(messages::id)
+        .first::<i32>(conn)
+        .await
+        .optional()?
+        .is_none()
+    {
+        return Err(AppError::from(StatusCode::NOT_FOUND, "Puzzle not found"));
+    }
+
+    // Whoever shares it first, possibly at the same time, picks the code
+    let inserted = diesel::insert_into(shared_puzzles::table)
+        .values((
+            shared_puzzles::code.eq(nanoid!(CODE_LENGTH)),
+            shared_puzzles::puzzle_type.eq(req.puzzle_type as i16),
+            shared_puzzles::message_id.eq(req.id),
+            shared_puzzles::seed.eq(req.seed as i64),
+            shared_puzzles::created_by.eq(auth.map(|Auth(claims)| claims.uid)),
+        ))
+        .on_conflict((
+            shared_puzzles::puzzle_type,
+            shared_puzzles::message_id,
+            shared_puzzles::seed,
+        ))
+        .do_nothing()
+        .returning(shared_puzzles::code)
+        .get_result::<String>(conn)
+        .await
+        .optional()?;
+
+    let code = match inserted {
+        Some(code) => code,
+        None => {
+            shared_puzzles::table
+                .select(shared_puzzles::code)
+                .filter(shared_puzzles::puzzle_type.eq(req.puzzle_type as i16))
+                .filter(shared_puzzles::message_id.eq(req.id))
+                .filter(shared_puzzles::seed.eq(req.seed as i64))
+                .first::<String>(conn)
+                .await?
+        }
+    };
+
+    Ok(Json(ShareResponse { code }))
+}
+
+async fn find(conn: &mut AsyncPgConnection, code: &str) -> AppResult<SharedPuzzle> {
+    shared_puzzles::table
+        .find(code)
+        .first::<SharedPuzzle>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "Puzzle not found"))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SharedResponse {
+    code: String,
+    puzzle_type: PuzzleType,
+    shared_by: Option<String>,
+    #[serde(flatten)]
+    puzzle: Puzzle,
+}
+
+/// Serves the exact puzzle behind a code, freshly signed for whoever asks.
+async fn shared(
+    State(state): State<AppState>,
+    auth: Option<Auth>,
+    Path(code): Path<String>,
+) -> AppResult<Json<SharedResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let shared = find(conn, &code).await?;
+    let message = messages::table
+        .find(shared.message_id)
+        .filter(messages::deleted_at.is_null())
+        .first::<Message>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "Puzzle not found"))?;
+    let shared_by = match &shared.created_by {
+        Some(uid) => users::table
+            .find(uid)
+            .select(users::username)
+            .first::<String>(conn)
+            .await
+            .optional()?,
+        None => None,
+    };
+
+    let puzzle_type = PuzzleType::try_from(shared.puzzle_type)?;
+    let seed = shared.seed as u32;
+    let puzzle = match puzzle_type {
+        PuzzleType::Aristocrat => {
+            Puzzle::Aristocrat(aristocrat::serve(&state, conn, &auth, message, seed).await?)
+        }
+        PuzzleType::Baconian => {
+            Puzzle::Baconian(baconian::serve(&state, conn, &auth, message, seed).await?)
+        }
+    };
+
+    Ok(Json(SharedResponse {
+        code: shared.code,
+        puzzle_type,
+        shared_by,
+        puzzle,
+    }))
+}
+
+/// Records the user's first solve of a shared puzzle so it can be compared
+/// with everyone else's who tried the same link. The seed the solve was signed
+/// with has to match too, so only solves of the shared key count.
+pub async fn record_solve(
+    conn: &mut AsyncPgConnection,
+    code: &str,
+    uid: &str,
+    solved: &Solved,
+) -> AppResult<()> {
+    let shared = find(conn, code).await?;
+    if shared.puzzle_type != solved.puzzle_type as i16
+        || shared.message_id != solved.message_id
+        || shared.seed != solved.seed as i64
+    {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "puzzle does not match the share code",
+        ));
+    }
+
+    diesel::insert_into(shared_solves::table)
+        .values((
+            shared_solves::code.eq(code),
+            shared_solves::user_id.eq(uid),
+            shared_solves::time_taken.eq(solved.time_taken as i32),
+            shared_solves::errors.eq(solved.grade.errors as i32),
+            shared_solves::score.eq(solved.grade.score),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SharedSolveResponse {
+    solver: String,
+    solved_at: String,
+    time_taken: i32,
+    errors: i32,
+    score: i32,
+}
+
+/// Everyone's solve of a shared puzzle, best first.
+async fn solves(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> AppResult<Json<Vec<SharedSolveResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let shared = find(conn, &code).await?;
+    let solves = shared_solves::table
+        .inner_join(users::table)
+        .select((
+            users::username,
+            shared_solves::solved_at,
+            shared_solves::time_taken,
+            shared_solves::errors,
+            shared_solves::score,
+        ))
+        .filter(shared_solves::code.eq(&shared.code))
+        .order((shared_solves::score.desc(), shared_solves::time_taken))
+        .load::<(String, DateTime<Local>, i32, i32, i32)>(conn)
+        .await?;
+
+    Ok(Json(
+        solves
+            .into_iter()
+            .map(
+                |(solver, solved_at, time_taken, errors, score)| SharedSolveResponse {
+                    solver,
+                    solved_at: format!("{}", solved_at.format("%F %I:%M %P")),
+                    time_taken,
+                    errors,
+                    score,
+                },
+            )
+            .collect(),
+    ))
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/share", post(share))
+        .route("/:code", get(shared))
+        .route("/:code/solves", get(solves))
+}
@@ -0,0 +1,204 @@
+use anyhow::anyhow;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Auth,
+    error::{AppError, AppResult},
+    exp::{self, ExpSource},
+    models::MessageSuggestion,
+    schema::{message_suggestions, messages, users},
+    AppState,
+};
+
+use super::admin::MessageFields;
+
+/// How many of a user's suggestions can wait for review at once.
+const MAX_PENDING: i64 = 20;
+
+#[repr(i16)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SuggestionStatus {
+    Pending = 0,
+    /// Added to the pool
+    Approved = 1,
+    Rejected = 2,
+}
+
+impl TryFrom<i16> for SuggestionStatus {
+    type Error = AppError;
+
+    fn try_from(v: i16) -> Result<Self, Self::Error> {
+        match v {
+            x if x == SuggestionStatus::Pending as i16 => Ok(SuggestionStatus::Pending),
+            x if x == SuggestionStatus::Approved as i16 => Ok(SuggestionStatus::Approved),
+            x if x == SuggestionStatus::Rejected as i16 => Ok(SuggestionStatus::Rejected),
+            _ => Err(AppError::InternalServerError(anyhow!(
+                "invalid SuggestionStatus"
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuggestionResponse {
+    id: i32,
+    submitted_by: String,
+    message: String,
+    attribution: Option<String>,
+    status: SuggestionStatus,
+    submitted_at: String,
+    reviewed_at: Option<String>,
+    /// Why it was rejected
+    reason: Option<String>,
+    /// The message it was added to the pool as
+    message_id: Option<i32>,
+    /// What the suggestion has earned the user
+    exp_sources: Vec<ExpSource>,
+}
+
+impl SuggestionResponse {
+    pub fn new(suggestion: MessageSuggestion, submitted_by: String) -> AppResult<Self> {
+        Ok(Self {
+            id: suggestion.id,
+            submitted_by,
+            message: suggestion.message,
+            attribution: suggestion.attribution,
+            status: SuggestionStatus::try_from(suggestion.status)?,
+            submitted_at: format!("{}", suggestion.submitted_at.format("%F %I:%M %P")),
+            reviewed_at: suggestion
+                .reviewed_at
+                .map(|t| format!("{}", t.format("%F %I:%M %P"))),
+            reason: suggestion.reason,
+            message_id: suggestion.message_id,
+            exp_sources: suggestion
+                .credited_exp
+                .map(|amount| ExpSource::special("Contributor Bonus", amount))
+                .into_iter()
+                .collect(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct SuggestRequest {
+    message: String,
+    attribution: Option<String>,
+}
+
+/// Puts a quote in the queue for an admin to add to the pool.
+async fn suggest(
+    State(state): State<AppState>,
+    Auth(claims): Auth,
+    Json(req): Json<SuggestRequest>,
+) -> AppResult<(StatusCode, Json<SuggestionResponse>)> {
+    let fields = MessageFields::new(&req.message, req.attribution.as_deref(), None, None);
+    fields
+        .validate()
+        .map_err(|e| AppError::from(StatusCode::BAD_REQUEST, e))?;
+
+    let conn = &mut state.db_pool.get().await?;
+
+    let pending = message_suggestions::table
+        .filter(message_suggestions::submitted_by.eq(&claims.uid))
+        .filter(message_suggestions::status.eq(SuggestionStatus::Pending as i16))
+        .count()
+        .get_result::<i64>(conn)
+        .await?;
+    if pending >= MAX_PENDING {
+        return Err(AppError::from(
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("at most {MAX_PENDING} suggestions can wait for review at once"),
+        ));
+    }
+
+    let in_pool = messages::table
+        .select(messages::id)
+        .filter(messages::message.eq(&fields.message))
+        .filter(messages::deleted_at.is_null())
+        .first::<i32>(conn)
+        .await
+        .optional()?;
+    if in_pool.is_some() {
+        return Err(AppError::from(
+            StatusCode::CONFLICT,
+            "message is already in the pool",
+        ));
+    }
+
+    let suggestion = diesel::insert_into(message_suggestions::table)
+        .values((
+            message_suggestions::submitted_by.eq(&claims.uid),
+            message_suggestions::message.eq(&fields.message),
+            message_suggestions::attribution.eq(&fields.attribution),
+        ))
+        .get_result::<MessageSuggestion>(conn)
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(SuggestionResponse::new(suggestion, claims.username)?),
+    ))
+}
+
+/// The user's own suggestions, newest first.
+async fn mine(
+    State(state): State<AppState>,
+    Auth(claims): Auth,
+) -> AppResult<Json<Vec<SuggestionResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let suggestions = message_suggestions::table
+        .filter(message_suggestions::submitted_by.eq(&claims.uid))
+        .order(message_suggestions::submitted_at.desc())
+        .load::<MessageSuggestion>(conn)
+        .await?;
+
+    Ok(Json(
+        suggestions
+            .into_iter()
+            .map(|suggestion| SuggestionResponse::new(suggestion, claims.username.clone()))
+            .collect::<AppResult<_>>()?,
+    ))
+}
+
+/// Gives the user who suggested a message exp the first time someone else
+/// solves it, recording it on the suggestion so it shows up in their list.
+pub async fn credit_contributor(
+    conn: &mut AsyncPgConnection,
+    message_id: i32,
+    solver: &str,
+) -> QueryResult<()> {
+    let Some(contributor) = diesel::update(message_suggestions::table)
+        .filter(message_suggestions::message_id.eq(message_id))
+        .filter(message_suggestions::credited_exp.is_null())
+        .filter(message_suggestions::submitted_by.ne(solver))
+        .set(message_suggestions::credited_exp.eq(exp::CONTRIBUTOR_BONUS))
+        .returning(message_suggestions::submitted_by)
+        .get_result::<String>(conn)
+        .await
+        .optional()? else {
+            return Ok(());
+        };
+
+    diesel::update(users::table.find(contributor))
+        .set(users::experience.eq(users::experience + exp::CONTRIBUTOR_BONUS))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/suggest", post(suggest))
+        .route("/suggestions", get(mine))
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, routing::get, Json, Router};
+use diesel::{dsl::count, prelude::*};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::{
+    error::AppResult,
+    schema::{message_tags, messages, tags},
+    AppState,
+};
+
+pub const MAX_TAG_LENGTH: usize = 32;
+
+/// Lowercases a tag name and checks it is made of letters, digits and dashes.
+pub fn normalize(name: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.chars().count() > MAX_TAG_LENGTH {
+        return Err(format!(
+            "tags must be between 1 and {MAX_TAG_LENGTH} characters"
+        ));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!(
+            "{name} is not a valid tag, use only letters, digits and dashes"
+        ));
+    }
+    Ok(name)
+}
+
+/// The tags of each of the messages, in alphabetical order.
+pub async fn of_messages(
+    conn: &mut AsyncPgConnection,
+    ids: &[i32],
+) -> QueryResult<HashMap<i32, Vec<String>>> {
+    let mut tags = HashMap::<i32, Vec<String>>::new();
+    for (id, name) in message_tags::table
+        .inner_join(tags::table)
+        .select((message_tags::message_id, tags::name))
+        .filter(message_tags::message_id.eq_any(ids))
+        .order(tags::name)
+        .load::<(i32, String)>(conn)
+        .await?
+    {
+        tags.entry(id).or_default().push(name);
+    }
+    Ok(tags)
+}
+
+#[derive(Serialize)]
+struct TagResponse {
+    name: String,
+    /// How many puzzles can be served with the tag
+    messages: i64,
+}
+
+/// Every tag that can be passed to `new`, with how many messages have it.
+async fn list(State(state): State<AppState>) -> AppResult<Json<Vec<TagResponse>>> {
+    let conn = &mut state.db_pool.get().await?;
+
+    let tags = tags::table
+        .left_join(message_tags::table)
+        .left_join(
+            messages::table.on(messages::id
+                .eq(message_tags::message_id)
+                .and(messages::deleted_at.is_null())),
+        )
+        .group_by(tags::name)
+        .select((tags::name, count(messages::id.nullable())))
+        .order(tags::name)
+        .load::<(String, i64)>(conn)
+        .await?;
+
+    Ok(Json(
+        tags.into_iter()
+            .map(|(name, messages)| TagResponse { name, messages })
+            .collect(),
+    ))
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new().route("/", get(list))
+}
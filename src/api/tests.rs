@@ -0,0 +1,560 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Html,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{Duration, Local};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::FutureExt;
+use nanoid::nanoid;
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{Auth, Coach, RequireRole},
+    error::{AppError, AppResult},
+    models::{Message, Test, TestQuestion},
+    print::{self, PrintedQuestion},
+    schema::{messages, test_questions, tests},
+    scoring::{self, Grade},
+    AppState,
+};
+
+use super::{aristocrat, baconian, pick_message, Avoid, Ciphertext, NewRequest, PuzzleType};
+
+const DEFAULT_TIME_LIMIT: i32 = 50 * 60;
+const MAX_TIME_LIMIT: i32 = 3 * 60 * 60;
+const MAX_QUESTIONS: usize = 40;
+/// Keeps a test's total score, bonus included, well inside an `i32`.
+const MAX_POINTS: i32 = 1000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuestionSpec {
+    puzzle_type: PuzzleType,
+    #[serde(default = "default_count")]
+    count: usize,
+    /// Defaults to what the puzzle type is usually worth
+    points: Option<i32>,
+    /// Whether this is the timed question, which earns a bonus for being
+    /// solved early. Only 1 question on a test can be timed.
+    #[serde(default)]
+    timed: bool,
+    min_difficulty: Option<f32>,
+    max_difficulty: Option<f32>,
+    /// Only messages with this tag, for themed tests
+    tag: Option<String>,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewTestRequest {
+    /// In seconds
+    time_limit: Option<i32>,
+    questions: Vec<QuestionSpec>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuestionResponse {
+    position: i16,
+    puzzle_type: PuzzleType,
+    ciphertext: Ciphertext,
+    attribution: String,
+    points: i32,
+    timed: bool,
+    answer: Option<String>,
+    /// Only filled in once the test is submitted
+    plaintext: Option<String>,
+    errors: Option<i32>,
+    score: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TestResponse {
+    id: String,
+    started_at: String,
+    time_limit: i32,
+    seconds_left: i64,
+    submitted: bool,
+    score: Option<i32>,
+    max_score: i32,
+    questions: Vec<QuestionResponse>,
+}
+
+impl TestResponse {
+    async fn new(conn: &mut AsyncPgConnection, test: Test) -> AppResult<Self> {
+        let questions = TestQuestion::belonging_to(&test)
+            .inner_join(messages::table)
+            .order(test_questions::position)
+            .load::<(TestQuestion, Message)>(conn)
+            .await?;
+
+        let submitted = test.submitted_at.is_some();
+        let mut responses = vec![];
+        for (question, message) in questions {
+            let puzzle_type = PuzzleType::try_from(question.puzzle_type)?;
+            responses.push(QuestionResponse {
+                position: question.position,
+                puzzle_type,
+                ciphertext: match puzzle_type {
+                    PuzzleType::Aristocrat => Ciphertext::Aristocrat(question.ciphertext),
+                    PuzzleType::Baconian => Ciphertext::Baconian(
+                        question.ciphertext.split(' ').map(String::from).collect(),
+                    ),
+                },
+                attribution: message.attribution.unwrap_or("Unknown".to_string()),
+                points: question.points,
+                timed: question.timed,
+                answer: question.answer,
+                plaintext: submitted.then_some(message.message),
+                errors: question.errors,
+                score: question.score,
+            });
+        }
+
+        let deadline = test.created_at + Duration::seconds(test.time_limit as i64);
+        Ok(Self {
+            id: test.id,
+            started_at: format!("{}", test.created_at.format("%F %I:%M %P")),
+            time_limit: test.time_limit,
+            seconds_left: (deadline - Local::now()).num_seconds().max(0),
+            submitted,
+            score: test.score,
+            max_score: responses.iter().map(|q| q.points).sum(),
+            questions: responses,
+        })
+    }
+}
+
+/// Checks the test fits the limits and returns its time limit.
+fn validate(req: &NewTestRequest) -> AppResult<i32> {
+    let time_limit = req.time_limit.unwrap_or(DEFAULT_TIME_LIMIT);
+    if !(60..=MAX_TIME_LIMIT).contains(&time_limit) {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "time limit must be between 1 minute and 3 hours",
+        ));
+    }
+
+    // Checked one spec at a time so a huge count can't overflow the total
+    let total = req.questions.iter().try_fold(0usize, |total, q| {
+        (q.count <= MAX_QUESTIONS)
+            .then(|| total.checked_add(q.count))
+            .flatten()
+    });
+    if !matches!(total, Some(total) if (1..=MAX_QUESTIONS).contains(&total)) {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            format!("a test must have between 1 and {MAX_QUESTIONS} questions"),
+        ));
+    }
+    if req
+        .questions
+        .iter()
+        .filter(|q| q.timed)
+        .map(|q| q.count)
+        .sum::<usize>()
+        > 1
+    {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            "only 1 question can be timed",
+        ));
+    }
+
+    if req
+        .questions
+        .iter()
+        .any(|q| matches!(q.points, Some(p) if !(1..=MAX_POINTS).contains(&p)))
+    {
+        return Err(AppError::from(
+            StatusCode::BAD_REQUEST,
+            format!("questions must be worth between 1 and {MAX_POINTS} points"),
+        ));
+    }
+
+    Ok(time_limit)
+}
+
+async fn new(
+    State(state): State<AppState>,
+    auth: Auth,
+    Json(req): Json<NewTestRequest>,
+) -> AppResult<Json<TestResponse>> {
+    let time_limit = validate(&req)?;
+
+    let conn = &mut state.db_pool.get().await?;
+
+    // All or nothing, so running out of messages doesn't leave half a test
+    let uid = auth.0.uid;
+    let test = conn
+        .transaction::<_, AppError, _>(|conn| {
+            async move {
+                let test = diesel::insert_into(tests::table)
+                    .values((
+                        tests::id.eq(nanoid!()),
+                        tests::user_id.eq(&uid),
+                        tests::time_limit.eq(time_limit),
+                    ))
+                    .get_result::<Test>(conn)
+                    .await?;
+
+                // Timed questions go first, like on a real test
+                let mut specs = req.questions;
+                specs.sort_by_key(|q| !q.timed);
+
+                let mut rng = StdRng::from_entropy();
+                let mut used = vec![];
+                let mut position = 0;
+                for spec in specs {
+                    let new_req = NewRequest {
+                        min_difficulty: spec.min_difficulty,
+                        max_difficulty: spec.max_difficulty,
+                        near_rating: false,
+                        tag: spec.tag.clone(),
+                    };
+                    for _ in 0..spec.count {
+                        let avoid = Avoid {
+                            solved_by: None,
+                            messages: &used,
+                        };
+                        let message =
+                            pick_message(conn, &mut rng, spec.puzzle_type, &None, &new_req, avoid)
+                                .await?;
+                        used.push(message.id);
+
+                        let ciphertext = match spec.puzzle_type {
+                            PuzzleType::Aristocrat => {
+                                aristocrat::encrypt(&mut rng, &message.message)
+                            }
+                            PuzzleType::Baconian => {
+                                baconian::encrypt(&mut rng, &message.message).join(" ")
+                            }
+                        };
+
+                        diesel::insert_into(test_questions::table)
+                            .values((
+                                test_questions::test_id.eq(&test.id),
+                                test_questions::position.eq(position),
+                                test_questions::puzzle_type.eq(spec.puzzle_type as i16),
+                                test_questions::message_id.eq(message.id),
+                                test_questions::ciphertext.eq(ciphertext),
+                                test_questions::points
+                                    .eq(spec.points.unwrap_or(scoring::points(spec.puzzle_type))),
+                                test_questions::timed.eq(spec.timed),
+                            ))
+                            .execute(conn)
+                            .await?;
+                        position += 1;
+                    }
+                }
+                Ok(test)
+            }
+            .boxed()
+        })
+        .await?;
+
+    Ok(Json(TestResponse::new(conn, test).await?))
+}
+
+/// Loads one of the user's tests.
+async fn find_test(conn: &mut AsyncPgConnection, auth: &Auth, id: String) -> AppResult<Test> {
+    tests::table
+        .filter(tests::id.eq(id))
+        .filter(tests::user_id.eq(&auth.0.uid))
+        .first::<Test>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::from(StatusCode::NOT_FOUND, "Test not found"))
+}
+
+async fn test(
+    State(state): State<AppState>,
+    auth: Auth,
+    Path(id): Path<String>,
+) -> AppResult<Json<TestResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let test = find_test(conn, &auth, id).await?;
+    Ok(Json(TestResponse::new(conn, test).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AnswerRequest {
+    position: i16,
+    answer: String,
+}
+
+async fn save_answer(
+    conn: &mut AsyncPgConnection,
+    test: &Test,
+    answer: AnswerRequest,
+) -> AppResult<()> {
+    let updated = diesel::update(test_questions::table)
+        .filter(test_questions::test_id.eq(&test.id))
+        .filter(test_questions::position.eq(answer.position))
+        .set((
+            test_questions::answer.eq(answer.answer),
+            test_questions::answered_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)
+        .await?;
+
+    if updated == 0 {
+        return Err(AppError::from(StatusCode::NOT_FOUND, "Question not found"));
+    }
+    Ok(())
+}
+
+fn ensure_open(test: &Test) -> AppResult<()> {
+    if test.submitted_at.is_some() {
+        return Err(already_submitted());
+    }
+    Ok(())
+}
+
+fn already_submitted() -> AppError {
+    AppError::from(StatusCode::CONFLICT, "test has already been submitted")
+}
+
+fn time_is_up(test: &Test) -> bool {
+    Local::now() > test.created_at + Duration::seconds(test.time_limit as i64)
+}
+
+async fn answer(
+    State(state): State<AppState>,
+    auth: Auth,
+    Path(id): Path<String>,
+    Json(req): Json<AnswerRequest>,
+) -> AppResult<Json<TestResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let test = find_test(conn, &auth, id).await?;
+
+    ensure_open(&test)?;
+    if time_is_up(&test) {
+        return Err(AppError::from(StatusCode::FORBIDDEN, "time is up"));
+    }
+
+    save_answer(conn, &test, req).await?;
+    Ok(Json(TestResponse::new(conn, test).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubmitTestRequest {
+    /// Answers to save before scoring, if there is still time
+    #[serde(default)]
+    answers: Vec<AnswerRequest>,
+}
+
+/// Scores every question on the test and stores the results for review.
+async fn submit(
+    State(state): State<AppState>,
+    auth: Auth,
+    Path(id): Path<String>,
+    Json(req): Json<SubmitTestRequest>,
+) -> AppResult<Json<TestResponse>> {
+    let conn = &mut state.db_pool.get().await?;
+    let test = find_test(conn, &auth, id).await?;
+
+    let test = conn
+        .transaction::<_, AppError, _>(|conn| {
+            async move {
+                // Locked so a concurrent submit waits for this one, then finds
+                // the test already submitted
+                let test = tests::table
+                    .find(&test.id)
+                    .for_update()
+                    .first::<Test>(conn)
+                    .await?;
+                ensure_open(&test)?;
+                if !time_is_up(&test) {
+                    for answer in req.answers {
+                        save_answer(conn, &test, answer).await?;
+                    }
+                }
+
+                let questions = TestQuestion::belonging_to(&test)
+                    .inner_join(messages::table)
+                    .load::<(TestQuestion, Message)>(conn)
+                    .await?;
+
+                let mut total = 0;
+                for (question, message) in questions {
+                    let puzzle_type = PuzzleType::try_from(question.puzzle_type)?;
+                    let Grade { errors, score, .. } = match &question.answer {
+                        Some(answer) => {
+                            scoring::grade(puzzle_type, &message.message, answer, question.points)
+                        }
+                        None => Grade {
+                            errors: message
+                                .message
+                                .chars()
+                                .filter(|c| c.is_ascii_alphabetic())
+                                .count(),
+                            error_positions: vec![],
+                            score: 0,
+                            points: question.points,
+                        },
+                    };
+
+                    let mut score = score;
+                    if let (true, true, Some(answered_at)) = (
+                        question.timed,
+                        errors <= scoring::FREE_ERRORS,
+                        question.answered_at,
+                    ) {
+                        score +=
+                            scoring::timed_bonus((answered_at - test.created_at).num_seconds());
+                    }
+                    total += score;
+
+                    diesel::update(test_questions::table)
+                        .filter(test_questions::test_id.eq(&test.id))
+                        .filter(test_questions::position.eq(question.position))
+                        .set((
+                            test_questions::errors.eq(errors as i32),
+                            test_questions::score.eq(score),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                diesel::update(tests::table)
+                    .filter(tests::id.eq(&test.id))
+                    .filter(tests::submitted_at.is_null())
+                    .set((
+                        tests::submitted_at.eq(diesel::dsl::now),
+                        tests::score.eq(total),
+                    ))
+                    .get_result::<Test>(conn)
+                    .await
+                    .optional()?
+                    .ok_or_else(already_submitted)
+            }
+            .boxed()
+        })
+        .await?;
+
+    Ok(Json(TestResponse::new(conn, test).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PrintRequest {
+    /// Regenerates a test printed before. A random one is used if left out.
+    seed: Option<u32>,
+    /// Print the answer key instead of the question booklet
+    #[serde(default)]
+    answer_key: bool,
+    #[serde(flatten)]
+    test: NewTestRequest,
+}
+
+/// Renders a paper test, or its answer key, as printable HTML for a coach.
+/// Nothing is recorded, so the same seed and questions print the same test
+/// as long as the pool stays the same.
+async fn print(
+    State(state): State<AppState>,
+    _: RequireRole<Coach>,
+    Json(req): Json<PrintRequest>,
+) -> AppResult<Html<String>> {
+    let time_limit = validate(&req.test)?;
+    let seed = req.seed.unwrap_or_else(|| thread_rng().gen());
+    // A named algorithm, unlike StdRng, gives the same numbers in every version
+    let mut rng = ChaCha8Rng::seed_from_u64(seed as u64);
+
+    let conn = &mut state.db_pool.get().await?;
+
+    let mut specs = req.test.questions;
+    specs.sort_by_key(|q| !q.timed);
+
+    let mut used = vec![];
+    let mut questions = vec![];
+    for spec in specs {
+        let new_req = NewRequest {
+            min_difficulty: spec.min_difficulty,
+            max_difficulty: spec.max_difficulty,
+            near_rating: false,
+            tag: spec.tag.clone(),
+        };
+        for _ in 0..spec.count {
+            let avoid = Avoid {
+                solved_by: None,
+                messages: &used,
+            };
+            let message =
+                pick_message(conn, &mut rng, spec.puzzle_type, &None, &new_req, avoid).await?;
+            used.push(message.id);
+
+            let ciphertext = match spec.puzzle_type {
+                PuzzleType::Aristocrat => aristocrat::encrypt(&mut rng, &message.message),
+                PuzzleType::Baconian => baconian::encrypt(&mut rng, &message.message).join(" "),
+            };
+
+            questions.push(PrintedQuestion {
+                puzzle_type: spec.puzzle_type,
+                points: spec.points.unwrap_or(scoring::points(spec.puzzle_type)),
+                timed: spec.timed,
+                ciphertext,
+                plaintext: message.message,
+                attribution: message.attribution.unwrap_or("Unknown".to_string()),
+            });
+        }
+    }
+
+    Ok(Html(if req.answer_key {
+        print::answer_key(seed, &questions)
+    } else {
+        print::booklet(seed, time_limit, &questions)
+    }))
+}
+
+pub fn app() -> Router<AppState> {
+    Router::new()
+        .route("/new", post(new))
+        .route("/print", post(print))
+        .route("/:id", get(test))
+        .route("/:id/answer", post(answer))
+        .route("/:id/submit", post(submit))
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn validate_json(req: serde_json::Value) -> AppResult<i32> {
+        validate(&serde_json::from_value(req).unwrap())
+    }
+
+    #[test]
+    fn question_counts_are_bounded() {
+        let spec = |count: usize| json!({"puzzleType": "Aristocrat", "count": count});
+        assert!(validate_json(json!({"questions": [spec(MAX_QUESTIONS)]})).is_ok());
+        assert!(validate_json(json!({"questions": [spec(MAX_QUESTIONS + 1)]})).is_err());
+        assert!(validate_json(json!({"questions": [spec(0)]})).is_err());
+        // Would wrap around to 1 if summed unchecked
+        assert!(validate_json(json!({"questions": [spec(usize::MAX), spec(2)]})).is_err());
+    }
+
+    #[test]
+    fn points_are_bounded() {
+        let spec = |points: i32| json!({"puzzleType": "Baconian", "points": points});
+        assert!(validate_json(json!({"questions": [spec(1), spec(MAX_POINTS)]})).is_ok());
+        for points in [0, -5, MAX_POINTS + 1, i32::MAX] {
+            assert!(validate_json(json!({"questions": [spec(points)]})).is_err());
+        }
+    }
+}
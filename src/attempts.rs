@@ -0,0 +1,151 @@
+use diesel::{
+    dsl::{count_star, sql},
+    prelude::*,
+    sql_types::BigInt,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::{api::PuzzleType, schema::puzzle_attempts, util::get_timestamp};
+
+/// A puzzle left unsolved for this long counts as abandoned.
+pub const ABANDON_AFTER: u128 = 60 * 60 * 1000;
+
+/// Records that the user was served a puzzle signed at `timestamp`.
+pub async fn start(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+    message_id: i32,
+    timestamp: u128,
+) -> QueryResult<()> {
+    diesel::insert_into(puzzle_attempts::table)
+        .values((
+            puzzle_attempts::user_id.eq(uid),
+            puzzle_attempts::puzzle_type.eq(puzzle_type as i16),
+            puzzle_attempts::message_id.eq(message_id),
+            puzzle_attempts::started_at.eq(timestamp as i64),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// How many of the user's last puzzles of a type to avoid serving again.
+pub const RECENT_ATTEMPTS: i64 = 50;
+
+/// The messages of the user's most recent puzzles of this type.
+pub async fn recent_messages(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+) -> QueryResult<Vec<i32>> {
+    puzzle_attempts::table
+        .select(puzzle_attempts::message_id)
+        .filter(puzzle_attempts::user_id.eq(uid))
+        .filter(puzzle_attempts::puzzle_type.eq(puzzle_type as i16))
+        .order(puzzle_attempts::started_at.desc())
+        .limit(RECENT_ATTEMPTS)
+        .load::<i32>(conn)
+        .await
+}
+
+/// Counts a wrong submission against the attempt. Submissions for puzzles the
+/// user was never served are ignored.
+pub async fn record_wrong(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+    message_id: i32,
+    timestamp: u128,
+) -> QueryResult<()> {
+    diesel::update(puzzle_attempts::table)
+        .filter(puzzle_attempts::user_id.eq(uid))
+        .filter(puzzle_attempts::puzzle_type.eq(puzzle_type as i16))
+        .filter(puzzle_attempts::message_id.eq(message_id))
+        .filter(puzzle_attempts::started_at.eq(timestamp as i64))
+        .filter(puzzle_attempts::solved_at.is_null())
+        .set(puzzle_attempts::wrong_submissions.eq(puzzle_attempts::wrong_submissions + 1))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Marks the attempt as solved and returns how many wrong submissions came
+/// before it, or `None` if the user was never served the puzzle or already
+/// solved it.
+pub async fn finish(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+    message_id: i32,
+    timestamp: u128,
+) -> QueryResult<Option<i32>> {
+    diesel::update(puzzle_attempts::table)
+        .filter(puzzle_attempts::user_id.eq(uid))
+        .filter(puzzle_attempts::puzzle_type.eq(puzzle_type as i16))
+        .filter(puzzle_attempts::message_id.eq(message_id))
+        .filter(puzzle_attempts::started_at.eq(timestamp as i64))
+        .filter(puzzle_attempts::solved_at.is_null())
+        .set(puzzle_attempts::solved_at.eq(diesel::dsl::now))
+        .returning(puzzle_attempts::wrong_submissions)
+        .get_result::<i32>(conn)
+        .await
+        .optional()
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptStats {
+    pub attempts: i64,
+    pub solved: i64,
+    pub abandoned: i64,
+    pub wrong_submissions: i64,
+}
+
+impl AttemptStats {
+    /// Fraction of submissions that were correct, if there were any.
+    pub fn accuracy(&self) -> Option<f32> {
+        let submissions = self.solved + self.wrong_submissions;
+        if submissions > 0 {
+            Some(self.solved as f32 / submissions as f32)
+        } else {
+            None
+        }
+    }
+}
+
+/// Totals the user's attempts, optionally only those of one puzzle type.
+pub async fn stats(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: Option<PuzzleType>,
+) -> QueryResult<AttemptStats> {
+    let abandoned_before = (get_timestamp() - ABANDON_AFTER) as i64;
+
+    let mut query = puzzle_attempts::table
+        .filter(puzzle_attempts::user_id.eq(uid))
+        .select((
+            count_star(),
+            sql::<BigInt>("COUNT(solved_at)"),
+            sql::<BigInt>("COUNT(*) FILTER (WHERE solved_at IS NULL AND started_at < ")
+                .bind::<BigInt, _>(abandoned_before)
+                .sql(")"),
+            sql::<BigInt>("COALESCE(SUM(wrong_submissions), 0)"),
+        ))
+        .into_boxed();
+    if let Some(puzzle_type) = puzzle_type {
+        query = query.filter(puzzle_attempts::puzzle_type.eq(puzzle_type as i16));
+    }
+
+    let (attempts, solved, abandoned, wrong_submissions) =
+        query.get_result::<(i64, i64, i64, i64)>(conn).await?;
+
+    Ok(AttemptStats {
+        attempts,
+        solved,
+        abandoned,
+        wrong_submissions,
+    })
+}
@@ -1,5 +1,6 @@
-use std::{env, time::Duration};
+use std::{env, marker::PhantomData, str::FromStr, time::Duration};
 
+use anyhow::anyhow;
 use argon2::Argon2;
 use axum::{
     async_trait,
@@ -8,12 +9,12 @@ use axum::{
     http::{request::Parts, StatusCode},
     RequestPartsExt, TypedHeader,
 };
-use jsonwebtoken::{errors::Result as JwtResult, DecodingKey, EncodingKey};
+use jsonwebtoken::{DecodingKey, EncodingKey};
 use lazy_static::{__Deref, lazy_static};
 use password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
 use serde::{Deserialize, Serialize};
 
-use crate::{error::ResponseStatusError, models::User};
+use crate::{error::AppError, models::User, sessions, AppState};
 
 pub fn hash_password(password: impl AsRef<[u8]>) -> password_hash::Result<String> {
     let salt = SaltString::generate(&mut OsRng);
@@ -54,6 +55,11 @@ lazy_static! {
 pub struct Claims {
     pub uid: String,
     pub username: String,
+    /// The session the token was issued for
+    pub sid: String,
+    /// Tokens issued before roles were added are students
+    #[serde(default)]
+    pub role: Role,
     pub iat: u64,
     pub exp: u64,
 }
@@ -63,45 +69,144 @@ pub fn ensure_jwt_secret_is_valid() {
     KEYS.deref();
 }
 
-pub fn generate_jwt(user: &User, exp: Duration) -> JwtResult<String> {
+pub fn generate_jwt(user: &User, sid: &str, exp: Duration) -> anyhow::Result<String> {
+    let role = Role::try_from(user.role).map_err(|_| anyhow!("invalid Role {}", user.role))?;
     let timestamp = jsonwebtoken::get_current_timestamp();
-    jsonwebtoken::encode(
+    Ok(jsonwebtoken::encode(
         &Default::default(),
         &Claims {
             uid: user.id.clone(),
             username: user.username.clone(),
+            sid: sid.to_string(),
+            role,
             iat: timestamp,
             exp: timestamp + exp.as_secs(),
         },
         &KEYS.encoding,
-    )
+    )?)
 }
 
+/// A valid access token from a session that hasn't been logged out.
 #[derive(Debug, Clone)]
 pub struct Auth(pub Claims);
 
 #[async_trait]
-impl<S> FromRequestParts<S> for Auth
-where
-    S: Send + Sync,
-{
-    type Rejection = ResponseStatusError;
+impl FromRequestParts<AppState> for Auth {
+    type Rejection = AppError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
-            .map_err(|_| (StatusCode::BAD_REQUEST, "missing credentials"))?;
+            .map_err(|_| AppError::from(StatusCode::BAD_REQUEST, "missing credentials"))?;
 
         let claims =
             jsonwebtoken::decode::<Claims>(bearer.token(), &KEYS.decoding, &Default::default())
-                .map_err(|_| (StatusCode::BAD_REQUEST, "invalid token"))?
+                .map_err(|_| AppError::from(StatusCode::BAD_REQUEST, "invalid token"))?
                 .claims;
 
         if claims.exp < jsonwebtoken::get_current_timestamp() {
-            Err((StatusCode::UNAUTHORIZED, "token expired").into())
+            return Err(AppError::from(StatusCode::UNAUTHORIZED, "token expired"));
+        }
+
+        let conn = &mut state.db_pool.get().await?;
+        if !sessions::is_active(conn, &claims.sid).await? {
+            return Err(AppError::from(StatusCode::UNAUTHORIZED, "logged out"));
+        }
+        Ok(Auth(claims))
+    }
+}
+
+/// What a user is allowed to do. Each role can do everything the ones before
+/// it can.
+#[repr(i16)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    #[default]
+    Student = 0,
+    /// Can run classes
+    Coach = 1,
+    /// Can manage the message pool
+    Admin = 2,
+}
+
+impl TryFrom<i16> for Role {
+    type Error = AppError;
+
+    fn try_from(v: i16) -> Result<Self, Self::Error> {
+        match v {
+            x if x == Role::Student as i16 => Ok(Role::Student),
+            x if x == Role::Coach as i16 => Ok(Role::Coach),
+            x if x == Role::Admin as i16 => Ok(Role::Admin),
+            _ => Err(AppError::InternalServerError(anyhow!("invalid Role"))),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "student" => Ok(Role::Student),
+            "coach" => Ok(Role::Coach),
+            "admin" => Ok(Role::Admin),
+            _ => Err(anyhow!(
+                "unknown role {s}, expected student, coach or admin"
+            )),
+        }
+    }
+}
+
+/// A role that [`RequireRole`] can require.
+pub trait RoleMarker {
+    const ROLE: Role;
+}
+
+#[derive(Debug, Clone)]
+pub struct Coach;
+
+impl RoleMarker for Coach {
+    const ROLE: Role = Role::Coach;
+}
+
+#[derive(Debug, Clone)]
+pub struct Admin;
+
+impl RoleMarker for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Like [`Auth`], but only lets through users with at least the role `R`, like
+/// `RequireRole<Admin>`. The role comes from the token, so a changed role takes
+/// effect the next time the token is refreshed.
+#[derive(Debug, Clone)]
+pub struct RequireRole<R>(pub Claims, pub PhantomData<R>);
+
+#[async_trait]
+impl<R> FromRequestParts<AppState> for RequireRole<R>
+where
+    R: RoleMarker,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Auth(claims) = Auth::from_request_parts(parts, state).await?;
+
+        if claims.role >= R::ROLE {
+            Ok(RequireRole(claims, PhantomData))
         } else {
-            Ok(Auth(claims))
+            Err(AppError::from(
+                StatusCode::FORBIDDEN,
+                "you do not have permission to do this",
+            ))
         }
     }
 }
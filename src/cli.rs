@@ -0,0 +1,161 @@
+use std::{fs, path::Path};
+
+use anyhow::bail;
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use futures::FutureExt;
+
+use crate::{
+    api::PuzzleType,
+    auth::Role,
+    difficulty,
+    exp::{LevelCurve, LEVEL_CURVE},
+    import::{self, Format},
+    schema::{level_curves, messages, users},
+    sessions, DbPool,
+};
+
+/// Moves every user's exp from the `old` curve onto the configured `LEVEL_CURVE`,
+/// so nobody gains or loses a level when the curve changes. Run it right after
+/// changing `LEVEL_CURVE`. The curve is recorded in `level_curves`, so running
+/// it again, or with an `old` curve users aren't on, fails instead of rebasing
+/// twice.
+pub async fn rebase_levels(db_pool: &DbPool, old: &LevelCurve) -> anyhow::Result<()> {
+    let conn = &mut db_pool.get().await?;
+    let old_curve = old.clone();
+    let (old, new) = (old.to_string(), LEVEL_CURVE.to_string());
+
+    let rebased = conn
+        .transaction::<_, anyhow::Error, _>(|conn| {
+            async move {
+                diesel::sql_query("LOCK TABLE level_curves IN EXCLUSIVE MODE")
+                    .execute(conn)
+                    .await?;
+                let current = level_curves::table
+                    .select(level_curves::curve)
+                    .order(level_curves::id.desc())
+                    .first::<String>(conn)
+                    .await
+                    .optional()?;
+                match current {
+                    Some(current) if current == new => bail!("users are already on {new}"),
+                    Some(current) if current != old => {
+                        bail!("users are on {current}, not {old}")
+                    }
+                    _ => {}
+                }
+
+                let users = users::table
+                    .select((users::id, users::experience))
+                    .load::<(String, i32)>(conn)
+                    .await?;
+
+                let mut rebased = 0;
+                for (id, experience) in users {
+                    let new_experience = LEVEL_CURVE.rebase(&old_curve, experience);
+                    if new_experience != experience {
+                        diesel::update(users::table)
+                            .filter(users::id.eq(id))
+                            .set(users::experience.eq(new_experience))
+                            .execute(conn)
+                            .await?;
+                        rebased += 1;
+                    }
+                }
+
+                diesel::insert_into(level_curves::table)
+                    .values(level_curves::curve.eq(&new))
+                    .execute(conn)
+                    .await?;
+                Ok(rebased)
+            }
+            .boxed()
+        })
+        .await?;
+
+    println!("rebased {rebased} users onto {}", *LEVEL_CURVE);
+    Ok(())
+}
+
+/// Rates every message in the pool and stores it in `messages.difficulty`.
+pub async fn backfill_difficulty(db_pool: &DbPool) -> anyhow::Result<()> {
+    let conn = &mut db_pool.get().await?;
+
+    let messages = messages::table
+        .select((messages::id, messages::message))
+        .load::<(i32, String)>(conn)
+        .await?;
+
+    let count = messages.len();
+    for (id, message) in messages {
+        diesel::update(messages::table)
+            .filter(messages::id.eq(id))
+            .set((
+                messages::difficulty.eq(difficulty::rate(PuzzleType::Aristocrat, &message)),
+                messages::baconian_difficulty.eq(difficulty::rate(PuzzleType::Baconian, &message)),
+            ))
+            .execute(conn)
+            .await?;
+    }
+
+    println!("rated {count} messages");
+    Ok(())
+}
+
+/// Gives a user a role, e.g. to make the first admin. The role is carried in
+/// their access tokens, so they are logged out everywhere for it to take
+/// effect right away.
+pub async fn set_role(db_pool: &DbPool, username: &str, role: Role) -> anyhow::Result<()> {
+    let conn = &mut db_pool.get().await?;
+    let name = username.to_string();
+
+    let revoked = conn
+        .transaction::<_, anyhow::Error, _>(|conn| {
+            async move {
+                let uid = diesel::update(users::table)
+                    .filter(users::username.eq(&name))
+                    .set(users::role.eq(role as i16))
+                    .returning(users::id)
+                    .get_result::<String>(conn)
+                    .await
+                    .optional()?;
+                let Some(uid) = uid else {
+                    bail!("no user named {name}");
+                };
+                Ok(sessions::revoke_all(conn, &uid).await?)
+            }
+            .boxed()
+        })
+        .await?;
+
+    println!("{username} is now {role:?}, {revoked} sessions logged out");
+    Ok(())
+}
+
+/// Bulk imports messages from a CSV or JSON lines file, skipping near
+/// duplicates. The format is taken from the file's extension.
+pub async fn import_messages(db_pool: &DbPool, path: &str, dry_run: bool) -> anyhow::Result<()> {
+    let input = fs::read_to_string(path)?;
+    let format = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str()?.parse::<Format>().ok())
+        .unwrap_or_else(|| Format::detect(&input));
+
+    let conn = &mut db_pool.get().await?;
+    let report = import::import(conn, import::parse(format, &input), dry_run).await?;
+
+    for skipped in &report.skipped {
+        println!("line {}: skipped, {}", skipped.line, skipped.reason);
+    }
+    for rejected in &report.rejected {
+        println!("line {}: rejected, {}", rejected.line, rejected.reason);
+    }
+    println!(
+        "{} {}, {} skipped, {} rejected",
+        if dry_run { "would insert" } else { "inserted" },
+        report.inserted.len(),
+        report.skipped.len(),
+        report.rejected.len()
+    );
+    Ok(())
+}
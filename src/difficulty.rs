@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{api::PuzzleType, models::Message};
+
+/// Rates how hard a message is to solve as a type of puzzle, from 1 (easy) to
+/// 10 (hard).
+pub fn rate(puzzle_type: PuzzleType, message: &str) -> f32 {
+    match puzzle_type {
+        PuzzleType::Aristocrat => rate_substitution(message),
+        PuzzleType::Baconian => rate_baconian(message),
+    }
+}
+
+/// The rating stored for the message as a type of puzzle.
+pub fn of(message: &Message, puzzle_type: PuzzleType) -> Option<f32> {
+    match puzzle_type {
+        PuzzleType::Aristocrat => message.difficulty,
+        PuzzleType::Baconian => message.baconian_difficulty,
+    }
+}
+
+/// Rates how hard a message is to crack as a substitution cipher.
+///
+/// Short messages give fewer letters to run frequency analysis on, many unique
+/// letters mean more of the key has to be found, pattern words (words with a
+/// repeated letter such as "that" or "people") give solvers a foothold, and a
+/// flat letter distribution hides the usual e/t/a peaks.
+fn rate_substitution(message: &str) -> f32 {
+    let letters = message
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if letters.is_empty() {
+        return 1.0;
+    }
+
+    let length_score = ((120.0 - letters.len() as f32) / 90.0).clamp(0.0, 1.0);
+
+    let unique = letters.iter().collect::<HashSet<_>>().len();
+    let unique_score = ((unique as f32 - 10.0) / 16.0).clamp(0.0, 1.0);
+
+    let words = message
+        .split_whitespace()
+        .map(|w| {
+            w.chars()
+                .filter(|c| c.is_ascii_alphabetic())
+                .map(|c| c.to_ascii_lowercase())
+                .collect::<Vec<_>>()
+        })
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>();
+    let pattern_words = words
+        .iter()
+        .filter(|w| w.iter().collect::<HashSet<_>>().len() < w.len())
+        .count();
+    let pattern_score = 1.0 - pattern_words as f32 / words.len().max(1) as f32;
+
+    let mut frequencies = HashMap::new();
+    for c in &letters {
+        *frequencies.entry(c).or_insert(0) += 1;
+    }
+    let entropy = frequencies
+        .values()
+        .map(|n| {
+            let p = *n as f32 / letters.len() as f32;
+            -p * p.log2()
+        })
+        .sum::<f32>();
+    let entropy_score = ((entropy - 3.5) / 0.8).clamp(0.0, 1.0);
+
+    let score =
+        0.3 * length_score + 0.25 * unique_score + 0.2 * pattern_score + 0.25 * entropy_score;
+    1.0 + 9.0 * score
+}
+
+/// Rates how hard a message is to decode as a Baconian. Every group decodes
+/// the same way once the two halves of the variant are spotted, so it mostly
+/// comes down to how many groups there are. I/J and U/V share a group, so
+/// words with them take a little more guessing.
+fn rate_baconian(message: &str) -> f32 {
+    let letters = message
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if letters.is_empty() {
+        return 1.0;
+    }
+
+    let length_score = ((letters.len() as f32 - 25.0) / 125.0).clamp(0.0, 1.0);
+
+    let shared = letters
+        .iter()
+        .filter(|c| matches!(c, 'i' | 'j' | 'u' | 'v'))
+        .count();
+    let shared_score = (shared as f32 / letters.len() as f32 / 0.15).clamp(0.0, 1.0);
+
+    let score = 0.85 * length_score + 0.15 * shared_score;
+    1.0 + 9.0 * score
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    /// Entropy is summed in hash map order, so ratings can be off by rounding.
+    const TOLERANCE: f32 = 1e-4;
+
+    const QUOTE: &str = "The only thing we have to fear is fear itself.";
+
+    fn random_message(rng: &mut impl Rng) -> String {
+        let length = rng.gen_range(0..300);
+        (0..length)
+            .map(|_| match rng.gen_range(0..6) {
+                0 => ' ',
+                1 => rng.gen_range(b'!'..=b'@') as char,
+                _ => rng.gen_range(b'a'..=b'z') as char,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ratings_stay_in_bounds() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut messages = vec![
+            String::new(),
+            "123 !?".to_string(),
+            "a".to_string(),
+            "abcdefghijklmnopqrstuvwxyz".to_string(),
+            "ivju".repeat(100),
+            QUOTE.repeat(20),
+        ];
+        messages.extend((0..200).map(|_| random_message(&mut rng)));
+
+        for message in &messages {
+            for puzzle_type in [PuzzleType::Aristocrat, PuzzleType::Baconian] {
+                let rating = rate(puzzle_type, message);
+                assert!((1.0..=10.0).contains(&rating), "{message:?} {rating}");
+            }
+        }
+    }
+
+    #[test]
+    fn no_letters_is_easiest() {
+        assert_eq!(rate(PuzzleType::Aristocrat, ""), 1.0);
+        assert_eq!(rate(PuzzleType::Baconian, "42, 17!"), 1.0);
+    }
+
+    #[test]
+    fn only_letters_count() {
+        for puzzle_type in [PuzzleType::Aristocrat, PuzzleType::Baconian] {
+            let plain = rate(puzzle_type, QUOTE);
+            let noisy = rate(puzzle_type, &QUOTE.to_uppercase().replace('.', "!?"));
+            assert!((plain - noisy).abs() < TOLERANCE, "{plain} {noisy}");
+        }
+    }
+
+    #[test]
+    fn longer_substitutions_are_no_harder() {
+        // Repeating a message only changes its length
+        let mut previous = f32::MAX;
+        for n in 1..=6 {
+            let rating = rate(PuzzleType::Aristocrat, &[QUOTE].repeat(n).join(" "));
+            assert!(rating <= previous + TOLERANCE, "{n} {rating} {previous}");
+            previous = rating;
+        }
+        assert!(
+            rate(PuzzleType::Aristocrat, QUOTE) > rate(PuzzleType::Aristocrat, &QUOTE.repeat(4))
+        );
+    }
+
+    #[test]
+    fn longer_baconians_are_no_easier() {
+        let mut previous = f32::MIN;
+        for n in 1..=8 {
+            let rating = rate(PuzzleType::Baconian, &"abcde ".repeat(n * 5));
+            assert!(rating >= previous - TOLERANCE, "{n} {rating} {previous}");
+            previous = rating;
+        }
+        assert!(
+            rate(PuzzleType::Baconian, &"abcde".repeat(30)) > rate(PuzzleType::Baconian, "abcde")
+        );
+    }
+
+    #[test]
+    fn shared_baconian_groups_are_no_easier() {
+        let mut previous = f32::MIN;
+        for shared in 0..=10 {
+            let message = "i".repeat(shared) + &"a".repeat(50 - shared);
+            let rating = rate(PuzzleType::Baconian, &message);
+            assert!(
+                rating >= previous - TOLERANCE,
+                "{shared} {rating} {previous}"
+            );
+            previous = rating;
+        }
+    }
+}
@@ -1,17 +1,27 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::Serialize;
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::BTreeMap, time::Duration};
 
 #[derive(Debug, Clone)]
-pub struct ResponseStatusError(StatusCode, Cow<'static, str>);
+pub struct ResponseStatusError(StatusCode, Cow<'static, str>, Option<Duration>);
 
 impl ResponseStatusError {
     pub fn from(code: StatusCode, s: impl Into<Cow<'static, str>>) -> Self {
-        Self(code, s.into())
+        Self(code, s.into(), None)
+    }
+
+    /// Sends a `Retry-After` header, rounded up to whole seconds.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.2 = Some(retry_after);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.0
     }
 }
 
@@ -29,12 +39,65 @@ impl IntoResponse for ResponseStatusError {
             message: Cow<'static, str>,
         }
 
-        (
+        let mut response = (
             self.0,
             Json(AppErrorResponse {
                 status: self.0.as_u16(),
                 message: self.1,
             }),
+        )
+            .into_response();
+
+        if let Some(retry_after) = self.2 {
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+/// What is wrong with each field of a request, keyed by the field's name in
+/// the request body.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationErrors(BTreeMap<&'static str, Vec<Cow<'static, str>>>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, problem: impl Into<Cow<'static, str>>) {
+        self.0.entry(field).or_default().push(problem.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Fails with the errors if there are any.
+    pub fn check(self) -> Result<(), AppError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::ValidationError(self))
+        }
+    }
+}
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct ValidationErrorResponse {
+            status: u16,
+            message: &'static str,
+            errors: ValidationErrors,
+        }
+
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ValidationErrorResponse {
+                status: StatusCode::BAD_REQUEST.as_u16(),
+                message: "invalid request",
+                errors: self,
+            }),
         )
             .into_response()
     }
@@ -43,6 +106,7 @@ impl IntoResponse for ResponseStatusError {
 pub enum AppError {
     InternalServerError(anyhow::Error),
     ResponseStatusError(ResponseStatusError),
+    ValidationError(ValidationErrors),
 }
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -55,6 +119,7 @@ impl IntoResponse for AppError {
                     .into_response()
             }
             AppError::ResponseStatusError(rse) => rse.into_response(),
+            AppError::ValidationError(errors) => errors.into_response(),
         }
     }
 }
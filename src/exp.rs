@@ -1,3 +1,7 @@
+use std::{env, fmt, fs, str::FromStr};
+
+use anyhow::{bail, Context};
+use lazy_static::{__Deref, lazy_static};
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -12,15 +16,378 @@ impl ExpSource {
         Self {
             name: name.to_string(),
             amount: format!("+{amount}"),
-            special: false
+            special: false,
+        }
+    }
+
+    pub fn special<T: ToString>(name: T, amount: i32) -> Self {
+        Self {
+            name: name.to_string(),
+            amount: format!("+{amount}"),
+            special: true,
+        }
+    }
+
+    pub fn multiplier<T: ToString>(name: T, factor: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            amount: format!("x{factor:.2}"),
+            special: false,
         }
     }
 }
 
+/// Scales the base exp for a solve so a middling (5.5) puzzle is worth `base`,
+/// the easiest puzzles half that and the hardest one and a half times it.
+/// Puzzles that have not been rated yet are worth `base`.
+pub fn scale_by_difficulty(base: i32, difficulty: Option<f32>) -> i32 {
+    match difficulty {
+        Some(d) => (base as f32 * (0.5 + (d - 1.0) / 9.0)).round() as i32,
+        None => base,
+    }
+}
+
+/// Awarded for solving a puzzle without any wrong submissions.
+pub const FIRST_TRY_BONUS: i32 = 25;
+
+/// Awarded to the user who suggested a message the first time someone else
+/// solves it.
+pub const CONTRIBUTOR_BONUS: i32 = 100;
+
+/// Each day of a streak past the first adds 5% exp, up to 50%.
+pub fn streak_multiplier(streak: i32) -> f64 {
+    1.0 + (0.05 * (streak - 1).max(0) as f64).min(0.5)
+}
+
+/// How much exp each level takes to complete.
+#[derive(Debug, Clone)]
+pub enum LevelCurve {
+    /// Every level takes `step` exp.
+    Linear { step: i32 },
+    /// Level `n` takes `base + growth * (n - 1)` exp.
+    Quadratic { base: i32, growth: i32 },
+    /// Level `n` takes the `n`th entry, levels past the end of the table take
+    /// the last entry.
+    Table(LevelTable),
+}
+
+#[derive(Debug, Clone)]
+pub struct LevelTable {
+    required: Vec<i32>,
+    /// `starts[i]` is the exp needed to reach level `i + 1`, up to the level
+    /// after the end of the table
+    starts: Vec<i64>,
+}
+
+impl LevelTable {
+    pub fn new(required: Vec<i32>) -> Self {
+        let starts = std::iter::once(0)
+            .chain(required.iter().scan(0, |total, &r| {
+                *total += r as i64;
+                Some(*total)
+            }))
+            .collect();
+        Self { required, starts }
+    }
+
+    fn last(&self) -> i32 {
+        *self
+            .required
+            .last()
+            .expect("level table should not be empty")
+    }
+
+    fn level(&self, exp: i32) -> i32 {
+        let reached = self.starts.partition_point(|&start| start <= exp as i64);
+        if reached <= self.required.len() {
+            return reached as i32;
+        }
+
+        let past_end = (exp as i64 - self.starts[self.required.len()]) / self.last() as i64;
+        (self.required.len() as i64 + 1 + past_end).min(i32::MAX as i64) as i32
+    }
+
+    fn total_before(&self, level: i32) -> i128 {
+        let done = (level - 1).max(0) as usize;
+        match self.starts.get(done) {
+            Some(start) => *start as i128,
+            None => {
+                let past_end = (done - self.required.len()) as i128;
+                self.starts[self.required.len()] as i128 + past_end * self.last() as i128
+            }
+        }
+    }
+}
+
+impl LevelCurve {
+    /// Exp needed to go from `level` to `level + 1`.
+    pub fn exp_required(&self, level: i32) -> i32 {
+        match self {
+            LevelCurve::Linear { step } => *step,
+            LevelCurve::Quadratic { base, growth } => {
+                base.saturating_add(growth.saturating_mul(level - 1))
+            }
+            LevelCurve::Table(table) => table
+                .required
+                .get(level as usize - 1)
+                .copied()
+                .unwrap_or_else(|| table.last()),
+        }
+    }
+
+    /// Exp needed to reach the start of `level`, without saturating.
+    fn total_before(&self, level: i32) -> i128 {
+        let done = (level - 1).max(0) as i128;
+        match self {
+            LevelCurve::Linear { step } => *step as i128 * done,
+            LevelCurve::Quadratic { base, growth } => {
+                *base as i128 * done + *growth as i128 * done * (done - 1) / 2
+            }
+            LevelCurve::Table(table) => table.total_before(level),
+        }
+    }
+
+    /// Returns the level reached with `exp` and how far into that level it is.
+    fn locate(&self, exp: i32) -> (i32, i32) {
+        let exp = exp.max(0);
+        let level = match self {
+            LevelCurve::Linear { step } => (exp / step).saturating_add(1),
+            LevelCurve::Quadratic { base, growth } => {
+                // Solve base * n + growth * n * (n - 1) / 2 = exp for the n
+                // levels completed, then correct for rounding
+                let (base, growth, exp) = (*base as f64, *growth as f64, exp as f64);
+                let done = if growth == 0.0 {
+                    exp / base
+                } else {
+                    let b = base - growth / 2.0;
+                    (-b + (b * b + 2.0 * growth * exp).sqrt()) / growth
+                };
+                let mut level = (done as i32).saturating_add(1);
+                while level > 1 && self.total_before(level) > exp as i128 {
+                    level -= 1;
+                }
+                while level < i32::MAX && self.total_before(level + 1) <= exp as i128 {
+                    level += 1;
+                }
+                level
+            }
+            LevelCurve::Table(table) => table.level(exp),
+        };
+        (level, (exp as i128 - self.total_before(level)) as i32)
+    }
+
+    pub fn level(&self, exp: i32) -> i32 {
+        self.locate(exp).0
+    }
+
+    pub fn exp_through(&self, exp: i32) -> i32 {
+        self.locate(exp).1
+    }
+
+    /// Total exp needed to reach the start of `level`.
+    pub fn exp_for_level(&self, level: i32) -> i32 {
+        self.total_before(level).min(i32::MAX as i128) as i32
+    }
+
+    /// Converts exp earned under `old` to the exp that puts the user at the same
+    /// level, and the same fraction of the way through it, under this curve.
+    pub fn rebase(&self, old: &LevelCurve, exp: i32) -> i32 {
+        let (level, through) = old.locate(exp);
+        let progress =
+            through as i64 * self.exp_required(level) as i64 / old.exp_required(level) as i64;
+        self.exp_for_level(level).saturating_add(progress as i32)
+    }
+}
+
+impl Default for LevelCurve {
+    fn default() -> Self {
+        LevelCurve::Linear { step: 1000 }
+    }
+}
+
+/// Parses `linear:<step>`, `quadratic:<base>:<growth>` or `table:<path>`, where
+/// the file at `path` lists the exp required for each level.
+impl FromStr for LevelCurve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        let curve = match parts.as_slice() {
+            ["linear", step] => LevelCurve::Linear {
+                step: step.parse()?,
+            },
+            ["quadratic", base, growth] => LevelCurve::Quadratic {
+                base: base.parse()?,
+                growth: growth.parse()?,
+            },
+            ["table", path] => LevelCurve::Table(LevelTable::new(
+                fs::read_to_string(path)
+                    .with_context(|| format!("unable to read level table {path}"))?
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.parse())
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => bail!("unknown level curve {s}"),
+        };
+
+        let valid = match &curve {
+            LevelCurve::Linear { step } => *step > 0,
+            LevelCurve::Quadratic { base, growth } => *base > 0 && *growth >= 0,
+            LevelCurve::Table(table) => {
+                !table.required.is_empty() && table.required.iter().all(|r| *r > 0)
+            }
+        };
+        if !valid {
+            bail!("level curve {s} must require a positive amount of exp");
+        }
+
+        Ok(curve)
+    }
+}
+
+/// Writes the curve the way it is configured, except that a table lists its
+/// entries instead of the file they came from.
+impl fmt::Display for LevelCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelCurve::Linear { step } => write!(f, "linear:{step}"),
+            LevelCurve::Quadratic { base, growth } => write!(f, "quadratic:{base}:{growth}"),
+            LevelCurve::Table(table) => {
+                let required = table
+                    .required
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "table:{}", required.join(","))
+            }
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref LEVEL_CURVE: LevelCurve = env::var("LEVEL_CURVE")
+        .map(|curve| curve
+            .parse()
+            .expect("LEVEL_CURVE is not a valid level curve"))
+        .unwrap_or_default();
+}
+
+#[allow(unused_must_use)]
+pub fn ensure_level_curve_is_valid() {
+    LEVEL_CURVE.deref();
+}
+
 pub fn exp_to_level(exp: i32) -> i32 {
-    1 + exp / 1000
+    LEVEL_CURVE.level(exp)
 }
 
 pub fn exp_through(exp: i32) -> i32 {
-    exp % 1000
+    LEVEL_CURVE.exp_through(exp)
+}
+
+pub fn exp_required(exp: i32) -> i32 {
+    LEVEL_CURVE.exp_required(exp_to_level(exp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps through levels one at a time, the way the closed forms should
+    /// agree with.
+    fn locate_by_steps(curve: &LevelCurve, exp: i32) -> (i32, i32) {
+        let mut level = 1;
+        let mut remaining = exp;
+        while remaining >= curve.exp_required(level) {
+            remaining -= curve.exp_required(level);
+            level += 1;
+        }
+        (level, remaining)
+    }
+
+    fn curves() -> Vec<LevelCurve> {
+        vec![
+            LevelCurve::Linear { step: 1000 },
+            LevelCurve::Linear { step: 7 },
+            LevelCurve::Quadratic { base: 1, growth: 0 },
+            LevelCurve::Quadratic {
+                base: 500,
+                growth: 250,
+            },
+            LevelCurve::Quadratic { base: 3, growth: 1 },
+            LevelCurve::Table(LevelTable::new(vec![100, 300, 600])),
+            LevelCurve::Table(LevelTable::new(vec![1, 1, 1])),
+        ]
+    }
+
+    #[test]
+    fn locate_matches_stepping() {
+        for curve in curves() {
+            for exp in (0..5000).chain([9999, 12345, 100_000]) {
+                assert_eq!(
+                    curve.locate(exp),
+                    locate_by_steps(&curve, exp),
+                    "{curve} {exp}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locate_is_fast_for_huge_level_counts() {
+        // Levels stop at i32::MAX, with the rest of the exp left over
+        let curve = LevelCurve::Quadratic { base: 1, growth: 0 };
+        assert_eq!(curve.locate(i32::MAX), (i32::MAX, 1));
+
+        let curve = LevelCurve::Table(LevelTable::new(vec![1, 1]));
+        assert_eq!(curve.locate(i32::MAX - 1), (i32::MAX, 0));
+
+        let curve = LevelCurve::Quadratic { base: 1, growth: 1 };
+        let (level, through) = curve.locate(i32::MAX);
+        assert!(through < curve.exp_required(level));
+        assert_eq!(curve.exp_for_level(level) + through, i32::MAX);
+    }
+
+    #[test]
+    fn exp_for_level_is_the_start_of_the_level() {
+        for curve in curves() {
+            for level in 1..50 {
+                let start = curve.exp_for_level(level);
+                assert_eq!(curve.locate(start), (level, 0), "{curve} {level}");
+                assert_eq!(
+                    curve.level(start - 1),
+                    (level - 1).max(1),
+                    "{curve} {level}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rebase_keeps_level_and_progress() {
+        let old = LevelCurve::Linear { step: 1000 };
+        let new = LevelCurve::Table(LevelTable::new(vec![500, 1000, 2000]));
+
+        assert_eq!(new.rebase(&old, 0), 0);
+        assert_eq!(new.rebase(&old, 500), 250);
+        assert_eq!(new.rebase(&old, 1000), 500);
+        assert_eq!(new.rebase(&old, 2500), 1500 + 1000);
+        assert_eq!(new.rebase(&old, 3999), 3500 + 1998);
+
+        for exp in [0, 1, 999, 1000, 5555] {
+            assert_eq!(old.rebase(&old, exp), exp);
+        }
+    }
+
+    #[test]
+    fn display_round_trips_formulas() {
+        for spec in ["linear:1000", "quadratic:500:250"] {
+            assert_eq!(spec.parse::<LevelCurve>().unwrap().to_string(), spec);
+        }
+        assert_eq!(
+            LevelCurve::Table(LevelTable::new(vec![1, 2, 3])).to_string(),
+            "table:1,2,3"
+        );
+    }
 }
@@ -0,0 +1,382 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::anyhow;
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::admin::{self, MessageFields},
+    schema::messages,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "ndjson" | "json" => Ok(Format::JsonLines),
+            _ => Err(anyhow!("unknown format {s}, expected csv or jsonl")),
+        }
+    }
+}
+
+impl Format {
+    /// Guesses the format from the first line: JSON lines start with an
+    /// object, anything else is taken to be a CSV header.
+    pub fn detect(input: &str) -> Self {
+        match input.trim_start().starts_with('{') {
+            true => Format::JsonLines,
+            false => Format::Csv,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRow {
+    pub message: String,
+    pub attribution: Option<String>,
+    #[serde(alias = "patristocrat_hint", alias = "hint")]
+    pub patristocrat_hint: Option<String>,
+    pub language: Option<String>,
+}
+
+/// A row of the input, or why it couldn't be read, with the line it starts on.
+pub type ParsedRow = (usize, Result<ImportRow, String>);
+
+pub fn parse(format: Format, input: &str) -> Vec<ParsedRow> {
+    match format {
+        Format::Csv => parse_csv(input),
+        Format::JsonLines => input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| (i + 1, serde_json::from_str(line).map_err(|e| e.to_string())))
+            .collect(),
+    }
+}
+
+/// Splits CSV into records of fields, with the line each record starts on.
+/// Quoted fields may contain commas, newlines and doubled quotes.
+fn csv_records(input: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\n') | (false, '\r') => {
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|f| !f.is_empty()) {
+                    records.push((start, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start = line;
+            }
+            _ => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    record.push(field);
+    if record.iter().any(|f| !f.is_empty()) {
+        records.push((start, record));
+    }
+
+    records
+}
+
+/// Reads CSV with a header row naming its columns. Only `message` is required.
+fn parse_csv(input: &str) -> Vec<ParsedRow> {
+    let mut records = csv_records(input).into_iter();
+    let Some((header_line, header)) = records.next() else {
+        return vec![];
+    };
+
+    let columns = header
+        .iter()
+        .map(|name| name.trim().to_lowercase().replace([' ', '-'], "_"))
+        .collect::<Vec<_>>();
+    if !columns.iter().any(|c| c == "message") {
+        return vec![(header_line, Err("header has no message column".to_string()))];
+    }
+
+    records
+        .map(|(line, fields)| {
+            let mut row = ImportRow::default();
+            for (column, field) in columns.iter().zip(fields) {
+                match column.as_str() {
+                    "message" => row.message = field,
+                    "attribution" => row.attribution = Some(field),
+                    "patristocrat_hint" | "hint" => row.patristocrat_hint = Some(field),
+                    "language" => row.language = Some(field),
+                    _ => {}
+                }
+            }
+
+            if row.message.is_empty() {
+                (line, Err("message is empty".to_string()))
+            } else {
+                (line, Ok(row))
+            }
+        })
+        .collect()
+}
+
+/// Tidies up spreadsheet text: curly quotes and dashes become plain ones and
+/// runs of whitespace become a single space.
+pub fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{2018}' | '\u{2019}' | '\u{201B}' | '\u{2032}' => normalized.push('\''),
+            '\u{201C}' | '\u{201D}' | '\u{201F}' | '\u{2033}' => normalized.push('"'),
+            '\u{2010}'..='\u{2015}' => normalized.push('-'),
+            '\u{2026}' => normalized.push_str("..."),
+            _ => normalized.push(c),
+        }
+    }
+    normalized.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// What two messages that only differ in case, spacing or punctuation have in
+/// common.
+pub fn dedupe_key(message: &str) -> String {
+    message
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inserted {
+    pub line: usize,
+    /// Left out on a dry run
+    pub id: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotInserted {
+    pub line: usize,
+    pub reason: String,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub inserted: Vec<Inserted>,
+    /// Duplicates of messages already in the pool or earlier in the input
+    pub skipped: Vec<NotInserted>,
+    /// Rows that couldn't be read or don't fit in `messages`
+    pub rejected: Vec<NotInserted>,
+}
+
+/// Adds the rows to the message pool, skipping any that only differ from
+/// another message in case, spacing or punctuation. A dry run reports what
+/// would happen without inserting anything. Either every row that can be
+/// inserted is, or none are.
+pub async fn import(
+    conn: &mut AsyncPgConnection,
+    rows: Vec<ParsedRow>,
+    dry_run: bool,
+) -> QueryResult<ImportReport> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move { import_rows(conn, rows, dry_run).await }.boxed()
+    })
+    .await
+}
+
+async fn import_rows(
+    conn: &mut AsyncPgConnection,
+    rows: Vec<ParsedRow>,
+    dry_run: bool,
+) -> QueryResult<ImportReport> {
+    let mut seen = HashMap::new();
+    for (id, message, deleted) in messages::table
+        .select((
+            messages::id,
+            messages::message,
+            messages::deleted_at.is_not_null(),
+        ))
+        .load::<(i32, String, bool)>(conn)
+        .await?
+    {
+        // Point at the live message when a deleted one has the same text
+        if deleted {
+            seen.entry(dedupe_key(&message))
+                .or_insert(format!("duplicate of deleted message {id}"));
+        } else {
+            seen.insert(dedupe_key(&message), format!("duplicate of message {id}"));
+        }
+    }
+
+    let mut report = ImportReport::default();
+    for (line, row) in rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(reason) => {
+                report.rejected.push(NotInserted { line, reason });
+                continue;
+            }
+        };
+
+        let fields = MessageFields::new(
+            &normalize(&row.message),
+            row.attribution.as_deref().map(normalize).as_deref(),
+            row.patristocrat_hint.as_deref().map(normalize).as_deref(),
+            row.language.as_deref(),
+        );
+        if let Err(reason) = fields.validate() {
+            report.rejected.push(NotInserted { line, reason });
+            continue;
+        }
+
+        let key = dedupe_key(&fields.message);
+        if let Some(reason) = seen.get(&key) {
+            report.skipped.push(NotInserted {
+                line,
+                reason: reason.clone(),
+            });
+            continue;
+        }
+
+        if dry_run {
+            report.inserted.push(Inserted { line, id: None });
+            seen.insert(key, format!("duplicate of line {line}"));
+            continue;
+        }
+
+        match admin::insert(conn, &fields).await? {
+            Some(message) => {
+                report.inserted.push(Inserted {
+                    line,
+                    id: Some(message.id),
+                });
+                seen.insert(key, format!("duplicate of message {}", message.id));
+            }
+            // Added by someone else since the pool was loaded
+            None => report.skipped.push(NotInserted {
+                line,
+                reason: "duplicate of a message added during the import".to_string(),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(rows: Vec<ParsedRow>) -> Vec<(usize, Result<String, String>)> {
+        rows.into_iter()
+            .map(|(line, row)| (line, row.map(|row| row.message)))
+            .collect()
+    }
+
+    #[test]
+    fn csv_reads_columns_by_header() {
+        let rows = parse(
+            Format::Csv,
+            "Attribution,Message,Patristocrat Hint\nTwain,Hello there,word\n",
+        );
+        assert_eq!(rows.len(), 1);
+        let (line, row) = &rows[0];
+        let row = row.as_ref().unwrap();
+        assert_eq!(*line, 2);
+        assert_eq!(row.message, "Hello there");
+        assert_eq!(row.attribution.as_deref(), Some("Twain"));
+        assert_eq!(row.patristocrat_hint.as_deref(), Some("word"));
+        assert_eq!(row.language, None);
+    }
+
+    #[test]
+    fn csv_quoted_fields() {
+        let rows = parse(
+            Format::Csv,
+            "message\n\"one, \"\"two\"\"\"\n\"three\nfour\"\r\nfive\n",
+        );
+        assert_eq!(
+            messages(rows),
+            vec![
+                (2, Ok("one, \"two\"".to_string())),
+                (3, Ok("three\nfour".to_string())),
+                (5, Ok("five".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_rejects_bad_rows() {
+        assert_eq!(
+            messages(parse(Format::Csv, "attribution\nTwain\n")),
+            vec![(1, Err("header has no message column".to_string()))]
+        );
+        assert_eq!(
+            messages(parse(Format::Csv, "message,attribution\n,Twain\n\nhi\n")),
+            vec![
+                (2, Err("message is empty".to_string())),
+                (4, Ok("hi".to_string())),
+            ]
+        );
+        assert!(parse(Format::Csv, "").is_empty());
+    }
+
+    #[test]
+    fn detects_format() {
+        assert_eq!(Format::detect("  {\"message\": \"hi\"}"), Format::JsonLines);
+        assert_eq!(Format::detect("message\nhi"), Format::Csv);
+    }
+
+    #[test]
+    fn normalize_tidies_punctuation_and_spacing() {
+        assert_eq!(
+            normalize("  \u{201C}It\u{2019}s\u{201D}  \u{2014}\tfine\u{2026}\n"),
+            "\"It's\" - fine..."
+        );
+    }
+
+    #[test]
+    fn dedupe_key_ignores_case_spacing_and_punctuation() {
+        assert_eq!(dedupe_key("Hello, World!"), dedupe_key("hello world"));
+        assert_ne!(dedupe_key("Hello, World!"), dedupe_key("Hello, Word!"));
+    }
+
+    #[test]
+    fn dedupe_key_keeps_non_ascii_letters() {
+        assert_eq!(dedupe_key("Ça VA"), "çava");
+        assert_ne!(dedupe_key("résumé"), dedupe_key("rsum"));
+        assert_eq!(dedupe_key("ΣΟΦΙΑ"), dedupe_key("σοφια"));
+    }
+}
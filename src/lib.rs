@@ -1,18 +1,36 @@
 #![feature(array_zip)]
 #![feature(let_else)]
 #![feature(async_closure)]
+pub mod achievements;
 pub mod api;
+pub mod attempts;
 pub mod auth;
+pub mod cli;
+pub mod difficulty;
 pub mod error;
 pub mod exp;
+pub mod import;
+pub mod logging;
+pub mod mail;
 pub mod models;
+pub mod print;
+pub mod rate_limit;
+pub mod rating;
 pub mod schema;
+pub mod scoring;
+pub mod sessions;
+pub mod streak;
 pub mod util;
+pub mod validation;
+
+use std::sync::Arc;
 
 use axum::Router;
 use axum_extra::routing::SpaRouter;
 use deadpool::managed::Pool;
 use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
+use mail::Mailer;
+use rate_limit::LimitStore;
 use ring::hmac;
 
 pub type DbConnection = AsyncDieselConnectionManager<AsyncPgConnection>;
@@ -22,6 +40,8 @@ pub type DbPool = Pool<DbConnection>;
 pub struct AppState {
     pub db_pool: DbPool,
     pub hmac_key: hmac::Key,
+    pub mailer: Arc<dyn Mailer>,
+    pub limiter: Arc<dyn LimitStore>,
 }
 
 pub fn establish_connection(db_url: &str) -> DbPool {
@@ -0,0 +1,41 @@
+use std::env;
+
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Writes log records to stderr, one per line.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{} {:<5} {}: {}",
+                Local::now().format("%F %T%.3f"),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Starts logging at the level in `LOG_LEVEL`, `info` if it isn't set.
+pub fn init() {
+    let level = match env::var("LOG_LEVEL") {
+        Ok(level) => level
+            .parse::<LevelFilter>()
+            .expect("LOG_LEVEL must be off, error, warn, info, debug or trace"),
+        Err(_) => Level::Info.to_level_filter(),
+    };
+    log::set_logger(&LOGGER).expect("logger was already set");
+    log::set_max_level(level);
+}
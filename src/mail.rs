@@ -0,0 +1,159 @@
+use std::{env, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Context};
+use axum::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, Address, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use nanoid::nanoid;
+
+use crate::util::get_timestamp;
+
+/// Column limit of `users.email`.
+const MAX_ADDRESS_LENGTH: usize = 127;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Somewhere to send mail to users through.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Connects to `host` with STARTTLS.
+    pub fn new(host: &str, credentials: Option<Credentials>, from: &str) -> anyhow::Result<Self> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+            .context("invalid SMTP_HOST")?;
+        if let Some(credentials) = credentials {
+            transport = transport.credentials(credentials);
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            from: from.parse().context("invalid MAIL_FROM")?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse()?)
+            .subject(&mail.subject)
+            .body(mail.body.clone())?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Writes each mail to a file in `dir` instead of sending it, or prints it if
+/// there is no directory. For development and tests.
+pub struct FileMailer {
+    pub dir: Option<PathBuf>,
+}
+
+impl FileMailer {
+    fn render(mail: &Mail) -> String {
+        format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            mail.to, mail.subject, mail.body
+        )
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        match &self.dir {
+            Some(dir) => {
+                // The address is up to the user, so it stays out of the path
+                let path = dir.join(format!("{}-{}.eml", get_timestamp(), nanoid!()));
+                tokio::fs::write(&path, Self::render(mail))
+                    .await
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+            None => println!("{}", Self::render(mail)),
+        }
+        Ok(())
+    }
+}
+
+/// Keeps mail in memory so tests can read it.
+#[cfg(test)]
+#[derive(Default)]
+pub struct CaptureMailer {
+    sent: std::sync::Mutex<Vec<Mail>>,
+}
+
+#[cfg(test)]
+impl CaptureMailer {
+    /// Everything sent to `to`, oldest first.
+    pub fn sent_to(&self, to: &str) -> Vec<Mail> {
+        let sent = self.sent.lock().unwrap();
+        sent.iter().filter(|mail| mail.to == to).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Mailer for CaptureMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        self.sent.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
+
+/// Checks an email address is well formed and returns it lowercased.
+pub fn normalize_address(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    (email.len() <= MAX_ADDRESS_LENGTH && email.parse::<Address>().is_ok()).then_some(email)
+}
+
+/// Where links in mail point to, from `APP_URL`.
+pub fn app_url() -> String {
+    env::var("APP_URL").unwrap_or_else(|_| "http://localhost:8080".to_string())
+}
+
+/// Picks the mailer from `MAIL_TRANSPORT`:
+/// - `smtp` sends through `SMTP_HOST` from `MAIL_FROM`, logging in with
+///   `SMTP_USERNAME` and `SMTP_PASSWORD` if they are set
+/// - `file` writes to files in `MAIL_DIR`
+/// - `log` prints mail to stdout
+///
+/// There is no default, so a server can't quietly end up not sending mail.
+pub fn from_env() -> anyhow::Result<Arc<dyn Mailer>> {
+    match env::var("MAIL_TRANSPORT").as_deref() {
+        Ok("smtp") => {
+            let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+                _ => None,
+            };
+            Ok(Arc::new(SmtpMailer::new(
+                &env::var("SMTP_HOST").context("SMTP_HOST must be set")?,
+                credentials,
+                &env::var("MAIL_FROM").context("MAIL_FROM must be set")?,
+            )?))
+        }
+        Ok("file") => Ok(Arc::new(FileMailer {
+            dir: Some(env::var("MAIL_DIR").context("MAIL_DIR must be set")?.into()),
+        })),
+        Ok("log") => Ok(Arc::new(FileMailer { dir: None })),
+        Ok(other) => bail!("unknown MAIL_TRANSPORT {other}, expected smtp, file or log"),
+        Err(_) => bail!("MAIL_TRANSPORT must be set to smtp, file or log"),
+    }
+}
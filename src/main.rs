@@ -1,20 +1,75 @@
-use std::env;
+use std::{env, net::SocketAddr, process, sync::Arc};
 
-use cryptopuz::{establish_connection, AppState, auth};
+use cryptopuz::{
+    auth, cli, establish_connection, exp, logging, mail,
+    rate_limit::{self, MemoryLimitStore},
+    util, AppState,
+};
+
+const USAGE: &str = "usage: cryptopuz [serve | rebase-levels <old level curve> | \
+                     backfill-difficulty | set-role <username> <student | coach | admin> | \
+                     import-messages <csv or jsonl file> [--dry-run]]";
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
+    exp::ensure_level_curve_is_valid();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let db_pool = establish_connection(&env::var("DATABASE_URL").unwrap());
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] | ["serve"] => {}
+        ["rebase-levels", old] => {
+            let old = old.parse().expect("invalid old level curve");
+            cli::rebase_levels(&db_pool, &old).await.unwrap();
+            return;
+        }
+        ["backfill-difficulty"] => {
+            cli::backfill_difficulty(&db_pool).await.unwrap();
+            return;
+        }
+        ["set-role", username, role] => {
+            let role = role.parse().expect("invalid role");
+            cli::set_role(&db_pool, username, role).await.unwrap();
+            return;
+        }
+        ["import-messages", path, rest @ ..] if matches!(rest, [] | ["--dry-run"]) => {
+            cli::import_messages(&db_pool, path, !rest.is_empty())
+                .await
+                .unwrap();
+            return;
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+    }
+
+    logging::init();
     auth::ensure_jwt_secret_is_valid();
+    util::ensure_puzzle_secret_is_valid();
+    rate_limit::ensure_proxy_header_is_valid();
     let rng = ring::rand::SystemRandom::new();
     let hmac_key = ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng)
         .expect("Unable to generate HMAC key");
 
-    let db_pool = establish_connection(&env::var("DATABASE_URL").unwrap());
-    let app = cryptopuz::app().with_state(AppState { db_pool, hmac_key });
+    let mailer = mail::from_env().expect("invalid mail settings");
+
+    let app = cryptopuz::app().with_state(AppState {
+        db_pool,
+        hmac_key,
+        mailer,
+        limiter: Arc::new(MemoryLimitStore::default()),
+    });
 
     axum::Server::bind(&([0, 0, 0, 0], 8080).into())
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
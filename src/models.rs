@@ -1,4 +1,7 @@
-use crate::schema::{messages, solves, users};
+use crate::schema::{
+    achievements, custom_puzzles, message_suggestions, messages, shared_puzzles, solves,
+    test_questions, tests, users,
+};
 use chrono::{DateTime, Local};
 use diesel::prelude::*;
 
@@ -10,6 +13,10 @@ pub struct Message {
     pub message: String,
     pub patristocrat_hint: Option<String>,
     pub attribution: Option<String>,
+    pub difficulty: Option<f32>,
+    pub deleted_at: Option<DateTime<Local>>,
+    pub language: String,
+    pub baconian_difficulty: Option<f32>,
 }
 
 #[derive(Identifiable, Queryable)]
@@ -22,6 +29,9 @@ pub struct User {
     pub created_at: DateTime<Local>,
     pub solved: i32,
     pub experience: i32,
+    pub timezone: String,
+    pub role: i16,
+    pub email_verified_at: Option<DateTime<Local>>,
 }
 
 #[derive(Identifiable, Queryable, Associations)]
@@ -35,3 +45,81 @@ pub struct Solve {
     pub time_taken: i32,
     pub exp_gained: i32,
 }
+
+#[derive(Identifiable, Queryable, Debug)]
+#[diesel(table_name = achievements)]
+pub struct Achievement {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub kind: i16,
+    pub puzzle_type: Option<i16>,
+    pub threshold: i32,
+    pub exp_reward: i32,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[diesel(belongs_to(User), table_name = tests)]
+pub struct Test {
+    pub id: String,
+    pub user_id: String,
+    pub created_at: DateTime<Local>,
+    pub time_limit: i32,
+    pub submitted_at: Option<DateTime<Local>>,
+    pub score: Option<i32>,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[diesel(belongs_to(Test), primary_key(test_id, position), table_name = test_questions)]
+pub struct TestQuestion {
+    pub test_id: String,
+    pub position: i16,
+    pub puzzle_type: i16,
+    pub message_id: i32,
+    pub ciphertext: String,
+    pub points: i32,
+    pub timed: bool,
+    pub answer: Option<String>,
+    pub answered_at: Option<DateTime<Local>>,
+    pub errors: Option<i32>,
+    pub score: Option<i32>,
+}
+
+#[derive(Identifiable, Queryable)]
+#[diesel(primary_key(code), table_name = shared_puzzles)]
+pub struct SharedPuzzle {
+    pub code: String,
+    pub puzzle_type: i16,
+    pub message_id: i32,
+    pub seed: i64,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+#[derive(Identifiable, Queryable)]
+#[diesel(primary_key(code), table_name = custom_puzzles)]
+pub struct CustomPuzzle {
+    pub code: String,
+    pub author: String,
+    pub puzzle_type: i16,
+    pub plaintext: String,
+    pub attribution: Option<String>,
+    pub visibility: i16,
+    pub created_at: DateTime<Local>,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[diesel(belongs_to(User, foreign_key = submitted_by), table_name = message_suggestions)]
+pub struct MessageSuggestion {
+    pub id: i32,
+    pub submitted_by: String,
+    pub message: String,
+    pub attribution: Option<String>,
+    pub status: i16,
+    pub submitted_at: DateTime<Local>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Local>>,
+    pub reason: Option<String>,
+    pub message_id: Option<i32>,
+    pub credited_exp: Option<i32>,
+}
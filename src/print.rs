@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use crate::api::PuzzleType;
+
+/// A question as it appears on a printed test.
+pub struct PrintedQuestion {
+    pub puzzle_type: PuzzleType,
+    pub points: i32,
+    pub timed: bool,
+    /// Baconian groups are separated by spaces
+    pub ciphertext: String,
+    pub plaintext: String,
+    pub attribution: String,
+}
+
+const STYLE: &str = "\
+body { font-family: serif; max-width: 50em; margin: auto; }
+.question { page-break-inside: avoid; margin-bottom: 2em; }
+.ciphertext { font-family: monospace; font-size: 1.3em; line-height: 2.5em; letter-spacing: 0.1em; }
+table { border-collapse: collapse; font-family: monospace; }
+td, th { border: 1px solid black; width: 1.4em; height: 1.4em; text-align: center; }
+";
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn type_name(puzzle_type: PuzzleType) -> &'static str {
+    match puzzle_type {
+        PuzzleType::Aristocrat => "Aristocrat",
+        PuzzleType::Baconian => "Baconian",
+    }
+}
+
+fn page(title: &str, seed: u32, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+         <p>Seed: {seed}</p>\n{body}</body>\n</html>\n"
+    )
+}
+
+fn heading(out: &mut String, number: usize, question: &PrintedQuestion) {
+    let _ = writeln!(
+        out,
+        "<h2>Question {number}{} ({} points)</h2>",
+        if question.timed { " (Timed)" } else { "" },
+        question.points,
+    );
+}
+
+/// The frequency table Codebusters puts under an Aristocrat, with an empty row
+/// to fill in the replacements.
+fn frequency_table(out: &mut String, ciphertext: &str) {
+    let mut counts = [0; 26];
+    for c in ciphertext.chars().filter(|c| c.is_ascii_alphabetic()) {
+        counts[(c.to_ascii_lowercase() as u8 - b'a') as usize] += 1;
+    }
+
+    out.push_str("<table>\n<tr><th></th>");
+    for c in 'A'..='Z' {
+        let _ = write!(out, "<th>{c}</th>");
+    }
+    out.push_str("</tr>\n<tr><th>Frequency</th>");
+    for count in counts {
+        let _ = write!(
+            out,
+            "<td>{}</td>",
+            if count > 0 {
+                count.to_string()
+            } else {
+                String::new()
+            }
+        );
+    }
+    out.push_str("</tr>\n<tr><th>Replacement</th>");
+    out.push_str(&"<td></td>".repeat(26));
+    out.push_str("</tr>\n</table>\n");
+}
+
+/// Renders the question booklet handed to students.
+pub fn booklet(seed: u32, time_limit: i32, questions: &[PrintedQuestion]) -> String {
+    let mut body = format!("<p>Time limit: {} minutes</p>\n", time_limit / 60);
+
+    for (i, question) in questions.iter().enumerate() {
+        body.push_str("<div class=\"question\">\n");
+        heading(&mut body, i + 1, question);
+        let _ = writeln!(
+            body,
+            "<p>Solve this {} by {}.</p>\n<p class=\"ciphertext\">{}</p>",
+            type_name(question.puzzle_type),
+            escape(&question.attribution),
+            escape(&question.ciphertext.to_uppercase()),
+        );
+        if question.puzzle_type == PuzzleType::Aristocrat {
+            frequency_table(&mut body, &question.ciphertext);
+        }
+        body.push_str("</div>\n");
+    }
+
+    page("Practice Test", seed, &body)
+}
+
+/// Renders the answer key for the booklet generated from the same seed.
+pub fn answer_key(seed: u32, questions: &[PrintedQuestion]) -> String {
+    let mut body = String::new();
+
+    for (i, question) in questions.iter().enumerate() {
+        body.push_str("<div class=\"question\">\n");
+        heading(&mut body, i + 1, question);
+        let _ = writeln!(
+            body,
+            "<p>{}</p>\n<p class=\"ciphertext\">{}</p>\n<p>&mdash; {}</p>",
+            type_name(question.puzzle_type),
+            escape(&question.plaintext.to_uppercase()),
+            escape(&question.attribution),
+        );
+        body.push_str("</div>\n");
+    }
+
+    page("Answer Key", seed, &body)
+}
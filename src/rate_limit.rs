@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::HeaderName, request::Parts, StatusCode},
+    RequestPartsExt,
+};
+use lazy_static::{__Deref, lazy_static};
+
+use crate::error::{AppError, ResponseStatusError};
+
+lazy_static! {
+    /// The header a reverse proxy in front of the server puts the client's
+    /// address in, e.g. `X-Forwarded-For`, from `TRUSTED_PROXY_HEADER`. Only
+    /// set it if clients can't reach the server without going through the
+    /// proxy, or they could pick their own address.
+    static ref TRUSTED_PROXY_HEADER: Option<HeaderName> =
+        env::var("TRUSTED_PROXY_HEADER").ok().map(|header| {
+            header
+                .parse()
+                .expect("TRUSTED_PROXY_HEADER is not a valid header name")
+        });
+}
+
+#[allow(unused_must_use)]
+pub fn ensure_proxy_header_is_valid() {
+    TRUSTED_PROXY_HEADER.deref();
+}
+
+/// How many keys [`MemoryLimitStore`] holds. Once it is full, stale entries
+/// are dropped, and then the ones that failed longest ago.
+const MAX_ENTRIES: usize = 10_000;
+
+/// How many failures are allowed before a key is locked out, and for how long.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    /// Failures allowed before the first lockout
+    pub attempts: u32,
+    /// Failures are forgotten after this long without another one
+    pub window: Duration,
+    /// The first lockout, doubled for each failure after it
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+impl Limit {
+    fn lockout_after(&self, failures: u32) -> Option<Duration> {
+        let excess = failures.checked_sub(self.attempts)?;
+        let lockout = self
+            .lockout
+            .checked_mul(2u32.saturating_pow(excess))
+            .unwrap_or(self.max_lockout);
+        Some(lockout.min(self.max_lockout))
+    }
+}
+
+/// Where failures are counted. Keys are things like an IP or a username,
+/// prefixed with what is being limited.
+#[async_trait]
+pub trait LimitStore: Send + Sync {
+    /// How long until `key` can try again, if it is locked out.
+    async fn retry_after(&self, key: &str) -> Option<Duration>;
+
+    /// Counts a failure for `key`, locking it out once it is over `limit`.
+    async fn record_failure(&self, key: &str, limit: &Limit);
+
+    /// Forgets the failures of `key`.
+    async fn clear(&self, key: &str);
+}
+
+struct Entry {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+    window: Duration,
+}
+
+impl Entry {
+    fn is_stale(&self, now: Instant) -> bool {
+        !matches!(self.locked_until, Some(until) if until > now)
+            && now.duration_since(self.last_failure) > self.window
+    }
+}
+
+/// Counts failures in memory, so they are per process and lost on restart.
+pub struct MemoryLimitStore {
+    entries: Mutex<HashMap<String, Entry>>,
+    max_entries: usize,
+}
+
+impl Default for MemoryLimitStore {
+    fn default() -> Self {
+        Self::with_capacity(MAX_ENTRIES)
+    }
+}
+
+impl MemoryLimitStore {
+    pub fn with_capacity(max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::default(),
+            max_entries,
+        }
+    }
+
+    fn retry_after_at(&self, key: &str, now: Instant) -> Option<Duration> {
+        let entries = self.entries.lock().unwrap();
+        let until = entries.get(key)?.locked_until?;
+        until
+            .checked_duration_since(now)
+            .filter(|retry_after| !retry_after.is_zero())
+    }
+
+    fn record_failure_at(&self, key: &str, limit: &Limit, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            entries.retain(|_, entry| !entry.is_stale(now));
+            // Still full of live entries, so one has to go early
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_failure)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+
+        let entry = entries.entry(key.to_string()).or_insert(Entry {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+            window: limit.window,
+        });
+        if entry.is_stale(now) {
+            entry.failures = 0;
+        }
+        entry.failures += 1;
+        entry.last_failure = now;
+        entry.window = limit.window;
+        entry.locked_until = limit
+            .lockout_after(entry.failures)
+            .map(|lockout| now + lockout);
+    }
+}
+
+#[async_trait]
+impl LimitStore for MemoryLimitStore {
+    async fn retry_after(&self, key: &str) -> Option<Duration> {
+        self.retry_after_at(key, Instant::now())
+    }
+
+    async fn record_failure(&self, key: &str, limit: &Limit) {
+        self.record_failure_at(key, limit, Instant::now())
+    }
+
+    async fn clear(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// The address a request came from, for keying limits on. Behind a proxy it
+/// comes from `TRUSTED_PROXY_HEADER`, otherwise every client would share the
+/// proxy's address.
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let forwarded = TRUSTED_PROXY_HEADER
+            .deref()
+            .as_ref()
+            .and_then(|header| parts.headers.get(header)?.to_str().ok())
+            .and_then(last_forwarded);
+        if let Some(ip) = forwarded {
+            return Ok(ClientIp(ip));
+        }
+
+        let ConnectInfo(addr) = parts
+            .extract::<ConnectInfo<SocketAddr>>()
+            .await
+            .map_err(|e| anyhow!("no client address: {e}"))?;
+        Ok(ClientIp(addr.ip()))
+    }
+}
+
+/// The last address in a header like `X-Forwarded-For`, which is the one the
+/// proxy added. Any before it were sent by the client.
+fn last_forwarded(value: &str) -> Option<IpAddr> {
+    value.rsplit(',').next()?.trim().parse().ok()
+}
+
+/// The 429 for a locked out key.
+pub fn too_many_attempts(retry_after: Duration) -> AppError {
+    ResponseStatusError::from(
+        StatusCode::TOO_MANY_REQUESTS,
+        "too many attempts, try again later",
+    )
+    .with_retry_after(retry_after)
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: Limit = Limit {
+        attempts: 3,
+        window: Duration::from_secs(60),
+        lockout: Duration::from_secs(10),
+        max_lockout: Duration::from_secs(100),
+    };
+
+    #[test]
+    fn lockout_doubles_up_to_the_max() {
+        assert_eq!(LIMIT.lockout_after(0), None);
+        assert_eq!(LIMIT.lockout_after(2), None);
+        assert_eq!(LIMIT.lockout_after(3), Some(Duration::from_secs(10)));
+        assert_eq!(LIMIT.lockout_after(4), Some(Duration::from_secs(20)));
+        assert_eq!(LIMIT.lockout_after(6), Some(Duration::from_secs(80)));
+        assert_eq!(LIMIT.lockout_after(7), Some(Duration::from_secs(100)));
+        assert_eq!(
+            LIMIT.lockout_after(u32::MAX),
+            Some(Duration::from_secs(100))
+        );
+    }
+
+    #[test]
+    fn locks_out_once_over_the_limit() {
+        let store = MemoryLimitStore::default();
+        let now = Instant::now();
+        for _ in 0..2 {
+            store.record_failure_at("key", &LIMIT, now);
+        }
+        assert_eq!(store.retry_after_at("key", now), None);
+
+        store.record_failure_at("key", &LIMIT, now);
+        assert_eq!(
+            store.retry_after_at("key", now),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            store.retry_after_at("key", now + Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(
+            store.retry_after_at("key", now + Duration::from_secs(10)),
+            None
+        );
+        assert_eq!(store.retry_after_at("other", now), None);
+    }
+
+    #[test]
+    fn failures_are_forgotten_after_the_window() {
+        let store = MemoryLimitStore::default();
+        let now = Instant::now();
+        for _ in 0..2 {
+            store.record_failure_at("key", &LIMIT, now);
+        }
+
+        // Within the window the count carries on
+        let later = now + Duration::from_secs(30);
+        store.record_failure_at("key", &LIMIT, later);
+        assert!(store.retry_after_at("key", later).is_some());
+
+        // Past the lockout and the window it starts over
+        let much_later = later + Duration::from_secs(61);
+        store.record_failure_at("key", &LIMIT, much_later);
+        assert_eq!(store.retry_after_at("key", much_later), None);
+        assert_eq!(store.entries.lock().unwrap()["key"].failures, 1);
+    }
+
+    #[test]
+    fn locked_out_entries_are_not_stale() {
+        let store = MemoryLimitStore::default();
+        let now = Instant::now();
+        let long = Limit {
+            max_lockout: Duration::from_secs(1000),
+            lockout: Duration::from_secs(1000),
+            ..LIMIT
+        };
+        for _ in 0..3 {
+            store.record_failure_at("key", &long, now);
+        }
+
+        // Past the window but still locked out, so the count goes on
+        let later = now + Duration::from_secs(120);
+        store.record_failure_at("key", &long, later);
+        assert_eq!(store.entries.lock().unwrap()["key"].failures, 4);
+    }
+
+    #[test]
+    fn clear_forgets_failures() {
+        let store = MemoryLimitStore::default();
+        let now = Instant::now();
+        for _ in 0..3 {
+            store.record_failure_at("key", &LIMIT, now);
+        }
+        futures::executor::block_on(store.clear("key"));
+        assert_eq!(store.retry_after_at("key", now), None);
+    }
+
+    #[test]
+    fn evicts_stale_then_oldest_when_full() {
+        let store = MemoryLimitStore::with_capacity(2);
+        let now = Instant::now();
+        store.record_failure_at("stale", &LIMIT, now);
+        store.record_failure_at("old", &LIMIT, now + Duration::from_secs(50));
+        store.record_failure_at("new", &LIMIT, now + Duration::from_secs(70));
+        {
+            let entries = store.entries.lock().unwrap();
+            assert_eq!(entries.len(), 2);
+            assert!(!entries.contains_key("stale"));
+        }
+
+        store.record_failure_at("newest", &LIMIT, now + Duration::from_secs(80));
+        let entries = store.entries.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains_key("new") && entries.contains_key("newest"));
+    }
+
+    #[test]
+    fn full_store_still_counts_existing_keys() {
+        let store = MemoryLimitStore::with_capacity(1);
+        let now = Instant::now();
+        for _ in 0..3 {
+            store.record_failure_at("key", &LIMIT, now);
+        }
+        assert!(store.retry_after_at("key", now).is_some());
+    }
+
+    #[test]
+    fn last_forwarded_address_is_used() {
+        assert_eq!(
+            last_forwarded("203.0.113.7"),
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+        assert_eq!(
+            last_forwarded("10.1.2.3, 203.0.113.7"),
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+        assert_eq!(
+            last_forwarded("2001:db8::1"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(last_forwarded("203.0.113.7, unknown"), None);
+        assert_eq!(last_forwarded(""), None);
+    }
+}
@@ -0,0 +1,166 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::FutureExt;
+
+use crate::{
+    api::PuzzleType,
+    difficulty,
+    models::Message,
+    schema::{message_ratings, messages, ratings},
+};
+
+/// Rating of a user who has never solved a puzzle of a type.
+pub const DEFAULT_RATING: f32 = 1000.0;
+
+/// The difficulty of a puzzle that starts out rated [`DEFAULT_RATING`].
+const MIDDLE_DIFFICULTY: f32 = 5.5;
+
+/// How much each point of difficulty adds to a puzzle's starting rating.
+const RATING_PER_DIFFICULTY: f32 = 100.0;
+
+/// Puzzles start out rated by their difficulty, 550 for the easiest and 1450
+/// for the hardest.
+pub fn initial_puzzle_rating(difficulty: Option<f32>) -> f32 {
+    match difficulty {
+        Some(d) => DEFAULT_RATING + (d - MIDDLE_DIFFICULTY) * RATING_PER_DIFFICULTY,
+        None => DEFAULT_RATING,
+    }
+}
+
+/// A message's rating for queries that left join `message_ratings`, falling
+/// back to [`initial_puzzle_rating`] for puzzles nobody has solved yet.
+pub fn puzzle_rating_sql(puzzle_type: PuzzleType) -> String {
+    let difficulty = match puzzle_type {
+        PuzzleType::Aristocrat => "messages.difficulty",
+        PuzzleType::Baconian => "messages.baconian_difficulty",
+    };
+    format!(
+        "COALESCE(message_ratings.rating, \
+         {DEFAULT_RATING} + ({difficulty} - {MIDDLE_DIFFICULTY}) * {RATING_PER_DIFFICULTY}, \
+         {DEFAULT_RATING})"
+    )
+}
+
+/// How long a solve should take for it to count as a draw between the solver
+/// and the puzzle.
+pub fn par_time(puzzle_type: PuzzleType) -> f32 {
+    match puzzle_type {
+        PuzzleType::Aristocrat => 300_000.0,
+        PuzzleType::Baconian => 180_000.0,
+    }
+}
+
+/// Scores a solve from 1 (a win for the solver) to 0 (a win for the puzzle).
+/// Solving in half of par time or less is a win, one and a half times par or
+/// more is a loss.
+fn score(puzzle_type: PuzzleType, time_taken: i32) -> f32 {
+    (1.5 - time_taken as f32 / par_time(puzzle_type)).clamp(0.0, 1.0)
+}
+
+/// Ratings move faster while there are only a few solves to go on.
+fn k_factor(solves: i32) -> f32 {
+    if solves < 10 {
+        64.0
+    } else {
+        32.0
+    }
+}
+
+pub async fn user_rating(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+) -> QueryResult<f32> {
+    Ok(ratings::table
+        .select(ratings::rating)
+        .filter(ratings::user_id.eq(uid))
+        .filter(ratings::puzzle_type.eq(puzzle_type as i16))
+        .first::<f32>(conn)
+        .await
+        .optional()?
+        .unwrap_or(DEFAULT_RATING))
+}
+
+/// Plays the solve out as an Elo match between the user and the puzzle, and
+/// returns the user's new rating. Both ratings are locked while it is worked
+/// out, so concurrent solves don't overwrite each other.
+pub async fn update(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    puzzle_type: PuzzleType,
+    message_id: i32,
+    time_taken: i32,
+) -> QueryResult<f32> {
+    let uid = uid.to_string();
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            // Both rows have to exist to be locked
+            diesel::insert_into(ratings::table)
+                .values((
+                    ratings::user_id.eq(&uid),
+                    ratings::puzzle_type.eq(puzzle_type as i16),
+                    ratings::rating.eq(DEFAULT_RATING),
+                    ratings::solves.eq(0),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+            let message = messages::table
+                .find(message_id)
+                .first::<Message>(conn)
+                .await?;
+            diesel::insert_into(message_ratings::table)
+                .values((
+                    message_ratings::message_id.eq(message_id),
+                    message_ratings::puzzle_type.eq(puzzle_type as i16),
+                    message_ratings::rating
+                        .eq(initial_puzzle_rating(difficulty::of(&message, puzzle_type))),
+                    message_ratings::solves.eq(0),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+
+            let user = ratings::table
+                .filter(ratings::user_id.eq(&uid))
+                .filter(ratings::puzzle_type.eq(puzzle_type as i16));
+            let (user_rating, user_solves) = user
+                .select((ratings::rating, ratings::solves))
+                .for_update()
+                .first::<(f32, i32)>(conn)
+                .await?;
+            let puzzle = message_ratings::table
+                .filter(message_ratings::message_id.eq(message_id))
+                .filter(message_ratings::puzzle_type.eq(puzzle_type as i16));
+            let (puzzle_rating, puzzle_solves) = puzzle
+                .select((message_ratings::rating, message_ratings::solves))
+                .for_update()
+                .first::<(f32, i32)>(conn)
+                .await?;
+
+            let expected = 1.0 / (1.0 + 10_f32.powf((puzzle_rating - user_rating) / 400.0));
+            let delta = score(puzzle_type, time_taken) - expected;
+            let new_user_rating = user_rating + k_factor(user_solves) * delta;
+            let new_puzzle_rating = puzzle_rating - k_factor(puzzle_solves) * delta;
+
+            diesel::update(user)
+                .set((
+                    ratings::rating.eq(new_user_rating),
+                    ratings::solves.eq(ratings::solves + 1),
+                ))
+                .execute(conn)
+                .await?;
+            diesel::update(puzzle)
+                .set((
+                    message_ratings::rating.eq(new_puzzle_rating),
+                    message_ratings::solves.eq(message_ratings::solves + 1),
+                ))
+                .execute(conn)
+                .await?;
+
+            Ok(new_user_rating)
+        }
+        .boxed()
+    })
+    .await
+}
@@ -1,11 +1,166 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    achievements (id) {
+        id -> Int4,
+        name -> Varchar,
+        description -> Varchar,
+        kind -> Int2,
+        puzzle_type -> Nullable<Int2>,
+        threshold -> Int4,
+        exp_reward -> Int4,
+    }
+}
+
+diesel::table! {
+    custom_puzzles (code) {
+        code -> Varchar,
+        author -> Varchar,
+        puzzle_type -> Int2,
+        plaintext -> Varchar,
+        attribution -> Nullable<Varchar>,
+        visibility -> Int2,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    custom_solves (code, user_id) {
+        code -> Varchar,
+        user_id -> Varchar,
+        solved_at -> Timestamptz,
+        time_taken -> Int4,
+        errors -> Int4,
+        score -> Int4,
+    }
+}
+
+diesel::table! {
+    email_verifications (token_hash) {
+        token_hash -> Varchar,
+        user_id -> Varchar,
+        email -> Varchar,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    level_curves (id) {
+        id -> Int4,
+        curve -> Text,
+        applied_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    message_ratings (message_id, puzzle_type) {
+        message_id -> Int4,
+        puzzle_type -> Int2,
+        rating -> Float4,
+        solves -> Int4,
+    }
+}
+
+diesel::table! {
+    message_suggestions (id) {
+        id -> Int4,
+        submitted_by -> Varchar,
+        message -> Varchar,
+        attribution -> Nullable<Varchar>,
+        status -> Int2,
+        submitted_at -> Timestamptz,
+        reviewed_by -> Nullable<Varchar>,
+        reviewed_at -> Nullable<Timestamptz>,
+        reason -> Nullable<Varchar>,
+        message_id -> Nullable<Int4>,
+        credited_exp -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    message_tags (tag_id, message_id) {
+        tag_id -> Int4,
+        message_id -> Int4,
+    }
+}
+
 diesel::table! {
     messages (id) {
         id -> Int4,
         message -> Varchar,
         patristocrat_hint -> Nullable<Varchar>,
         attribution -> Nullable<Varchar>,
+        difficulty -> Nullable<Float4>,
+        deleted_at -> Nullable<Timestamptz>,
+        language -> Varchar,
+        baconian_difficulty -> Nullable<Float4>,
+    }
+}
+
+diesel::table! {
+    password_resets (token_hash) {
+        token_hash -> Varchar,
+        user_id -> Varchar,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    puzzle_attempts (id) {
+        id -> Int4,
+        user_id -> Varchar,
+        puzzle_type -> Int2,
+        message_id -> Int4,
+        started_at -> Int8,
+        wrong_submissions -> Int4,
+        solved_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    ratings (user_id, puzzle_type) {
+        user_id -> Varchar,
+        puzzle_type -> Int2,
+        rating -> Float4,
+        solves -> Int4,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        refresh_token_hash -> Varchar,
+        previous_token_hash -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        last_used_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    shared_puzzles (code) {
+        code -> Varchar,
+        puzzle_type -> Int2,
+        message_id -> Int4,
+        seed -> Int8,
+        created_by -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    shared_solves (code, user_id) {
+        code -> Varchar,
+        user_id -> Varchar,
+        solved_at -> Timestamptz,
+        time_taken -> Int4,
+        errors -> Int4,
+        score -> Int4,
     }
 }
 
@@ -21,6 +176,48 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    tags (id) {
+        id -> Int4,
+        name -> Varchar,
+    }
+}
+
+diesel::table! {
+    test_questions (test_id, position) {
+        test_id -> Varchar,
+        position -> Int2,
+        puzzle_type -> Int2,
+        message_id -> Int4,
+        ciphertext -> Text,
+        points -> Int4,
+        timed -> Bool,
+        answer -> Nullable<Text>,
+        answered_at -> Nullable<Timestamptz>,
+        errors -> Nullable<Int4>,
+        score -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    tests (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        created_at -> Timestamptz,
+        time_limit -> Int4,
+        submitted_at -> Nullable<Timestamptz>,
+        score -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    user_achievements (user_id, achievement_id) {
+        user_id -> Varchar,
+        achievement_id -> Int4,
+        unlocked_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Varchar,
@@ -30,14 +227,58 @@ diesel::table! {
         created_at -> Timestamptz,
         solved -> Int4,
         experience -> Int4,
+        timezone -> Varchar,
+        role -> Int2,
+        email_verified_at -> Nullable<Timestamptz>,
     }
 }
 
+diesel::joinable!(custom_puzzles -> users (author));
+diesel::joinable!(custom_solves -> custom_puzzles (code));
+diesel::joinable!(custom_solves -> users (user_id));
+diesel::joinable!(email_verifications -> users (user_id));
+diesel::joinable!(message_ratings -> messages (message_id));
+diesel::joinable!(message_suggestions -> messages (message_id));
+diesel::joinable!(message_suggestions -> users (submitted_by));
+diesel::joinable!(message_tags -> messages (message_id));
+diesel::joinable!(message_tags -> tags (tag_id));
+diesel::joinable!(password_resets -> users (user_id));
+diesel::joinable!(puzzle_attempts -> messages (message_id));
+diesel::joinable!(puzzle_attempts -> users (user_id));
+diesel::joinable!(ratings -> users (user_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(shared_puzzles -> messages (message_id));
+diesel::joinable!(shared_puzzles -> users (created_by));
+diesel::joinable!(shared_solves -> shared_puzzles (code));
+diesel::joinable!(shared_solves -> users (user_id));
 diesel::joinable!(solves -> messages (message_id));
 diesel::joinable!(solves -> users (solver));
+diesel::joinable!(test_questions -> messages (message_id));
+diesel::joinable!(test_questions -> tests (test_id));
+diesel::joinable!(tests -> users (user_id));
+diesel::joinable!(user_achievements -> achievements (achievement_id));
+diesel::joinable!(user_achievements -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    achievements,
+    custom_puzzles,
+    custom_solves,
+    email_verifications,
+    level_curves,
+    message_ratings,
+    message_suggestions,
+    message_tags,
     messages,
+    password_resets,
+    puzzle_attempts,
+    ratings,
+    sessions,
+    shared_puzzles,
+    shared_solves,
     solves,
+    tags,
+    test_questions,
+    tests,
+    user_achievements,
     users,
 );
@@ -0,0 +1,223 @@
+use serde::Serialize;
+
+use crate::api::PuzzleType;
+
+/// Errors allowed before a question starts losing points.
+pub const FREE_ERRORS: usize = 2;
+
+/// Points lost for each error past [`FREE_ERRORS`].
+pub const ERROR_PENALTY: i32 = 100;
+
+/// The timed question earns a bonus for each second under this it is solved in.
+pub const TIMED_BONUS_WINDOW: i64 = 10 * 60;
+
+/// What a question of each type is usually worth on a Codebusters test.
+pub fn points(puzzle_type: PuzzleType) -> i32 {
+    match puzzle_type {
+        PuzzleType::Aristocrat => 250,
+        PuzzleType::Baconian => 200,
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Grade {
+    pub errors: usize,
+    /// Character indices into the plaintext of the letters that were wrong or
+    /// missing
+    pub error_positions: Vec<usize>,
+    pub score: i32,
+    pub points: i32,
+}
+
+impl Grade {
+    /// Fraction of the question's points that were awarded. A question worth
+    /// no points has nothing to lose, so it gets full credit.
+    pub fn credit(&self) -> f64 {
+        if self.points <= 0 {
+            return 1.0;
+        }
+        self.score as f64 / self.points as f64
+    }
+}
+
+/// Baconian cannot tell i from j or u from v, so neither can its grading.
+fn normalize(puzzle_type: PuzzleType, c: char) -> char {
+    let c = c.to_ascii_lowercase();
+    match (puzzle_type, c) {
+        (PuzzleType::Baconian, 'j') => 'i',
+        (PuzzleType::Baconian, 'v') => 'u',
+        _ => c,
+    }
+}
+
+/// Letters past the end of the plaintext that are lined up with it. Any more
+/// are just counted as errors, which keeps the alignment small.
+const MAX_EXTRA_LETTERS: usize = 50;
+
+/// Grades a submission letter by letter against the plaintext, ignoring
+/// spacing and punctuation, and scores it out of `points` the way Codebusters
+/// does. The submission is lined up with the plaintext first, so a missing or
+/// extra letter is one error rather than throwing off every letter after it.
+pub fn grade(puzzle_type: PuzzleType, plaintext: &str, submission: &str, points: i32) -> Grade {
+    let expected = plaintext
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| c.is_ascii_alphabetic())
+        .map(|(i, c)| (i, normalize(puzzle_type, c)))
+        .collect::<Vec<_>>();
+    let mut submitted = submission
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| normalize(puzzle_type, c))
+        .collect::<Vec<_>>();
+    let overflow = submitted
+        .len()
+        .saturating_sub(expected.len() + MAX_EXTRA_LETTERS);
+    submitted.truncate(submitted.len() - overflow);
+
+    let (error_positions, extra) = align(&expected, &submitted);
+    let errors = error_positions.len() + extra + overflow;
+
+    let penalized = errors.saturating_sub(FREE_ERRORS) as i32;
+    Grade {
+        errors,
+        error_positions,
+        score: (points - penalized * ERROR_PENALTY).max(0),
+        points,
+    }
+}
+
+/// Lines `submitted` up with the `expected` letters by edit distance. Returns
+/// the positions of the expected letters that were wrong or missing, and how
+/// many extra letters were submitted.
+fn align(expected: &[(usize, char)], submitted: &[char]) -> (Vec<usize>, usize) {
+    // distances[i][j] is the edit distance between the first i expected and
+    // the first j submitted letters
+    let width = submitted.len() + 1;
+    let mut distances = vec![0; (expected.len() + 1) * width];
+    for i in 0..=expected.len() {
+        for j in 0..=submitted.len() {
+            distances[i * width + j] = match (i, j) {
+                (0, j) => j,
+                (i, 0) => i,
+                (i, j) => {
+                    let substitution = usize::from(expected[i - 1].1 != submitted[j - 1]);
+                    (distances[(i - 1) * width + j - 1] + substitution)
+                        .min(distances[(i - 1) * width + j] + 1)
+                        .min(distances[i * width + j - 1] + 1)
+                }
+            };
+        }
+    }
+
+    let mut error_positions = vec![];
+    let mut extra = 0;
+    let (mut i, mut j) = (expected.len(), submitted.len());
+    while i > 0 || j > 0 {
+        let distance = distances[i * width + j];
+        if i > 0 && j > 0 {
+            let substitution = usize::from(expected[i - 1].1 != submitted[j - 1]);
+            if distance == distances[(i - 1) * width + j - 1] + substitution {
+                if substitution == 1 {
+                    error_positions.push(expected[i - 1].0);
+                }
+                i -= 1;
+                j -= 1;
+                continue;
+            }
+        }
+        if i > 0 && distance == distances[(i - 1) * width + j] + 1 {
+            error_positions.push(expected[i - 1].0);
+            i -= 1;
+        } else {
+            extra += 1;
+            j -= 1;
+        }
+    }
+
+    error_positions.reverse();
+    (error_positions, extra)
+}
+
+/// Bonus for solving the timed question `seconds` into the test.
+pub fn timed_bonus(seconds: i64) -> i32 {
+    (4.5 * (TIMED_BONUS_WINDOW - seconds).max(0) as f64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAINTEXT: &str = "The quick, brown fox.";
+
+    fn aristocrat(submission: &str) -> Grade {
+        grade(PuzzleType::Aristocrat, PLAINTEXT, submission, 250)
+    }
+
+    #[test]
+    fn grade_ignores_case_spacing_and_punctuation() {
+        let grade = aristocrat("THEQUICKBROWNFOX");
+        assert_eq!(grade.errors, 0);
+        assert_eq!(grade.score, 250);
+    }
+
+    #[test]
+    fn grade_marks_wrong_letters_by_plaintext_position() {
+        let grade = aristocrat("the quack brown fix");
+        assert_eq!(grade.errors, 2);
+        assert_eq!(grade.error_positions, vec![6, 18]);
+        assert_eq!(grade.score, 250);
+    }
+
+    #[test]
+    fn grade_counts_a_missing_letter_once() {
+        let grade = aristocrat("the quick brwn fox");
+        assert_eq!(grade.errors, 1);
+        assert_eq!(grade.error_positions, vec![13]);
+    }
+
+    #[test]
+    fn grade_counts_an_extra_letter_once() {
+        let grade = aristocrat("thee quick brown fox");
+        assert_eq!(grade.errors, 1);
+        assert!(grade.error_positions.is_empty());
+    }
+
+    #[test]
+    fn grade_penalizes_errors_past_the_free_ones() {
+        assert_eq!(aristocrat("the quick brown f").score, 250);
+        assert_eq!(aristocrat("the quick brown").score, 150);
+        assert_eq!(aristocrat("").score, 0);
+        assert_eq!(aristocrat("").errors, 16);
+    }
+
+    #[test]
+    fn grade_bounds_long_submissions() {
+        let grade = aristocrat(&"z".repeat(10_000));
+        assert_eq!(grade.errors, 10_000);
+        assert_eq!(grade.score, 0);
+    }
+
+    #[test]
+    fn baconian_grading_merges_shared_letters() {
+        let grade = grade(PuzzleType::Baconian, "jive", "iiue", 200);
+        assert_eq!(grade.errors, 0);
+    }
+
+    #[test]
+    fn points_follow_codebusters() {
+        assert_eq!(points(PuzzleType::Aristocrat), 250);
+        assert_eq!(points(PuzzleType::Baconian), 200);
+    }
+
+    #[test]
+    fn credit_is_the_fraction_of_points_scored() {
+        assert_eq!(aristocrat("the quick brown").credit(), 0.6);
+        assert_eq!(aristocrat("").credit(), 0.0);
+        assert_eq!(
+            grade(PuzzleType::Aristocrat, PLAINTEXT, "", 0).credit(),
+            1.0
+        );
+    }
+}
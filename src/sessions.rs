@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use chrono::Local;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use nanoid::nanoid;
+
+use crate::{
+    schema::sessions,
+    util::{hash_token, new_secret_token},
+};
+
+/// How long an access token is accepted for before it has to be refreshed.
+pub const ACCESS_TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// How long a session lasts without being refreshed.
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+fn expires_at() -> chrono::DateTime<Local> {
+    Local::now() + chrono::Duration::from_std(SESSION_LIFETIME).expect("lifetime fits")
+}
+
+/// Starts a session for the user and returns its id and refresh token.
+pub async fn start(conn: &mut AsyncPgConnection, uid: &str) -> QueryResult<(String, String)> {
+    let id = nanoid!();
+    let token = new_secret_token();
+
+    diesel::insert_into(sessions::table)
+        .values((
+            sessions::id.eq(&id),
+            sessions::user_id.eq(uid),
+            sessions::refresh_token_hash.eq(hash_token(&token)),
+            sessions::expires_at.eq(expires_at()),
+        ))
+        .execute(conn)
+        .await?;
+
+    Ok((id, token))
+}
+
+pub enum Refreshed {
+    /// The session's id, its user and the refresh token that replaces the one
+    /// that was used
+    Rotated {
+        id: String,
+        uid: String,
+        token: String,
+    },
+    /// The token was already used, so someone else may have it. The session
+    /// has been revoked.
+    Reused,
+    Invalid,
+}
+
+/// Swaps a refresh token for a new one, extending the session.
+pub async fn refresh(conn: &mut AsyncPgConnection, token: &str) -> QueryResult<Refreshed> {
+    let hash = hash_token(token);
+    let new_token = new_secret_token();
+
+    let rotated = diesel::update(sessions::table)
+        .filter(sessions::refresh_token_hash.eq(&hash))
+        .filter(sessions::revoked_at.is_null())
+        .filter(sessions::expires_at.gt(diesel::dsl::now))
+        .set((
+            sessions::refresh_token_hash.eq(hash_token(&new_token)),
+            sessions::previous_token_hash.eq(&hash),
+            sessions::last_used_at.eq(diesel::dsl::now),
+            sessions::expires_at.eq(expires_at()),
+        ))
+        .returning((sessions::id, sessions::user_id))
+        .get_result::<(String, String)>(conn)
+        .await
+        .optional()?;
+    if let Some((id, uid)) = rotated {
+        return Ok(Refreshed::Rotated {
+            id,
+            uid,
+            token: new_token,
+        });
+    }
+
+    let reused = diesel::update(sessions::table)
+        .filter(sessions::previous_token_hash.eq(&hash))
+        .filter(sessions::revoked_at.is_null())
+        .set(sessions::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await?;
+    if reused > 0 {
+        Ok(Refreshed::Reused)
+    } else {
+        Ok(Refreshed::Invalid)
+    }
+}
+
+/// Whether access tokens from the session are still accepted.
+pub async fn is_active(conn: &mut AsyncPgConnection, id: &str) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+        sessions::table
+            .find(id)
+            .filter(sessions::revoked_at.is_null())
+            .filter(sessions::expires_at.gt(diesel::dsl::now)),
+    ))
+    .get_result::<bool>(conn)
+    .await
+}
+
+pub async fn revoke(conn: &mut AsyncPgConnection, id: &str) -> QueryResult<()> {
+    diesel::update(sessions::table.find(id))
+        .filter(sessions::revoked_at.is_null())
+        .set(sessions::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Logs the user out everywhere.
+pub async fn revoke_all(conn: &mut AsyncPgConnection, uid: &str) -> QueryResult<usize> {
+    diesel::update(sessions::table)
+        .filter(sessions::user_id.eq(uid))
+        .filter(sessions::revoked_at.is_null())
+        .set(sessions::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await
+}
+
+/// Logs the user out everywhere but the session `keep`.
+pub async fn revoke_others(
+    conn: &mut AsyncPgConnection,
+    uid: &str,
+    keep: &str,
+) -> QueryResult<usize> {
+    diesel::update(sessions::table)
+        .filter(sessions::user_id.eq(uid))
+        .filter(sessions::id.ne(keep))
+        .filter(sessions::revoked_at.is_null())
+        .set(sessions::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .await
+}
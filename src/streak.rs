@@ -0,0 +1,135 @@
+use chrono::{Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    sql_types::{Date, Text},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::{models::User, schema::solves};
+
+#[derive(Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct Streak {
+    /// Consecutive days with a solve, ending today or yesterday.
+    pub current: i32,
+    pub longest: i32,
+}
+
+/// Parses an IANA timezone name such as `America/Chicago`.
+pub fn parse_timezone(timezone: &str) -> Option<Tz> {
+    timezone.parse().ok()
+}
+
+/// Computes the user's streaks from `solves.solved_at`, splitting days in the
+/// user's timezone. With `solving_today` the solve currently being submitted is
+/// counted, since it has not been recorded yet.
+pub async fn streak(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    solving_today: bool,
+) -> QueryResult<Streak> {
+    let tz = parse_timezone(&user.timezone).unwrap_or(Tz::UTC);
+
+    let days = solves::table
+        .filter(solves::solver.eq(&user.id))
+        .select(
+            sql::<Date>("(solved_at AT TIME ZONE ")
+                .bind::<Text, _>(tz.name())
+                .sql(")::date"),
+        )
+        .distinct()
+        .load::<NaiveDate>(conn)
+        .await?;
+
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    Ok(from_days(days, today, solving_today))
+}
+
+/// Computes streaks from the days the user solved on, in any order and with
+/// repeats. The current streak is 0 unless it reaches today or yesterday.
+pub fn from_days(mut days: Vec<NaiveDate>, today: NaiveDate, solving_today: bool) -> Streak {
+    if solving_today {
+        days.push(today);
+    }
+    days.sort_unstable_by(|a, b| b.cmp(a));
+    days.dedup();
+
+    let mut streak = Streak::default();
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in &days {
+        if matches!(previous, Some(p) if p - Duration::days(1) != *day) {
+            if streak.current == 0 {
+                streak.current = run;
+            }
+            run = 0;
+        }
+        run += 1;
+        streak.longest = streak.longest.max(run);
+        previous = Some(*day);
+    }
+    if streak.current == 0 {
+        streak.current = run;
+    }
+
+    match days.as_slice() {
+        [last, ..] if *last >= today - Duration::days(1) => {}
+        _ => streak.current = 0,
+    }
+
+    streak
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 3, d).unwrap()
+    }
+
+    fn streak_of(days: &[u32], today: u32, solving_today: bool) -> (i32, i32) {
+        let streak = from_days(
+            days.iter().map(|&d| day(d)).collect(),
+            day(today),
+            solving_today,
+        );
+        (streak.current, streak.longest)
+    }
+
+    #[test]
+    fn no_solves() {
+        assert_eq!(streak_of(&[], 10, false), (0, 0));
+        assert_eq!(streak_of(&[], 10, true), (1, 1));
+    }
+
+    #[test]
+    fn streak_including_today() {
+        assert_eq!(streak_of(&[8, 9, 10], 10, false), (3, 3));
+        assert_eq!(streak_of(&[8, 9], 10, true), (3, 3));
+    }
+
+    #[test]
+    fn streak_still_open_from_yesterday() {
+        assert_eq!(streak_of(&[7, 8, 9], 10, false), (3, 3));
+    }
+
+    #[test]
+    fn broken_streak() {
+        // Last solved the day before yesterday
+        assert_eq!(streak_of(&[6, 7, 8], 10, false), (0, 3));
+        assert_eq!(streak_of(&[6, 7, 8], 10, true), (1, 3));
+        // A gap in the middle keeps only the latest run as current
+        assert_eq!(streak_of(&[1, 2, 3, 4, 8, 9, 10], 10, false), (3, 4));
+    }
+
+    #[test]
+    fn duplicate_and_unordered_days() {
+        assert_eq!(streak_of(&[10, 9, 9, 10, 8, 8], 10, false), (3, 3));
+        // Solving again on a day already counted doesn't add to it
+        assert_eq!(streak_of(&[9, 10], 10, true), (2, 2));
+    }
+}
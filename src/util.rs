@@ -1,10 +1,57 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use diesel::sql_function;
-use ring::hmac;
+use diesel::{sql_function, sql_types::Text};
+use lazy_static::{__Deref, lazy_static};
+use nanoid::nanoid;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use ring::{digest, hmac};
 
 use crate::auth::Auth;
 
+sql_function!(fn lower(x: Text) -> Text);
+
+lazy_static! {
+    /// Unlike the key puzzles are signed with, this stays the same across
+    /// restarts so seeded puzzles can be regenerated.
+    static ref PUZZLE_KEY: hmac::Key = {
+        let secret = env::var("PUZZLE_SECRET").expect("PUZZLE_SECRET must be set");
+        hmac::Key::new(
+            hmac::HMAC_SHA256,
+            &base64::decode(secret).expect("PUZZLE_SECRET is not valid base64"),
+        )
+    };
+}
+
+#[allow(unused_must_use)]
+pub fn ensure_puzzle_secret_is_valid() {
+    PUZZLE_KEY.deref();
+}
+
+/// The rng a puzzle's cipher key is drawn from. It is seeded with an HMAC of
+/// the seed rather than the seed itself, so knowing the seed isn't enough to
+/// work out the key.
+pub fn key_rng(seed: u32) -> ChaCha8Rng {
+    rng_from_tag(&seed.to_le_bytes())
+}
+
+/// The rng a custom puzzle's cipher key is drawn from. It is keyed by the
+/// puzzle's code under its own prefix, so an author who knows their plaintext
+/// can't learn anything about the keys `key_rng` gives out.
+pub fn custom_key_rng(code: &str) -> ChaCha8Rng {
+    rng_from_tag(format!("custom:{code}").as_bytes())
+}
+
+fn rng_from_tag(msg: &[u8]) -> ChaCha8Rng {
+    let tag = hmac::sign(&PUZZLE_KEY, msg);
+    let mut key_seed = [0; 32];
+    key_seed.copy_from_slice(tag.as_ref());
+    ChaCha8Rng::from_seed(key_seed)
+}
+
 pub fn get_timestamp() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -33,7 +80,7 @@ pub fn generate_sig(
     ))
 }
 
-pub fn verify_solution(
+pub fn verify_sig(
     hmac_key: &hmac::Key,
     auth: &Option<Auth>,
     msg_id: i32,
@@ -57,4 +104,20 @@ pub fn verify_solution(
     .is_ok())
 }
 
-sql_function!(fn random() -> Text);
+/// Long enough to be unguessable: 43 characters of 64 is 258 bits.
+const SECRET_TOKEN_LENGTH: usize = 43;
+
+/// A token for refreshing a session or following a link from an email.
+pub fn new_secret_token() -> String {
+    nanoid!(SECRET_TOKEN_LENGTH)
+}
+
+/// Secret tokens are only stored hashed, so a leaked table can't be used to
+/// log in.
+pub fn hash_token(token: &str) -> String {
+    digest::digest(&digest::SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
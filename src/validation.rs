@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+
+use crate::error::ValidationErrors;
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+/// Column limit of `users.username`.
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// Keeps hashing cheap enough that long passwords can't tie up the server.
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Passwords this short on variety are too easy to guess, like `aaaaaaaa` or
+/// `12121212`.
+const MIN_DISTINCT_CHARACTERS: usize = 4;
+
+/// Names that could pass for the site itself or clash with routes.
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "anonymous",
+    "api",
+    "assets",
+    "coach",
+    "coverage",
+    "cryptopuz",
+    "help",
+    "login",
+    "logout",
+    "me",
+    "mod",
+    "moderator",
+    "null",
+    "official",
+    "register",
+    "root",
+    "settings",
+    "staff",
+    "support",
+    "system",
+    "undefined",
+];
+
+const COMMON_PASSWORDS: &[&str] = &[
+    "11111111",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "1q2w3e4r",
+    "abc12345",
+    "abcd1234",
+    "baseball",
+    "football",
+    "iloveyou",
+    "letmein1",
+    "password",
+    "password1",
+    "password123",
+    "princess",
+    "qwerty123",
+    "qwertyuiop",
+    "sunshine",
+    "trustno1",
+    "welcome1",
+];
+
+/// Usernames are ASCII letters, numbers, `_` and `-`, so that names can't be
+/// told apart only by lookalike characters. Uniqueness ignores case, which is
+/// up to the database.
+pub fn check_username(errors: &mut ValidationErrors, field: &'static str, username: &str) {
+    let length = username.chars().count();
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+        errors.add(
+            field,
+            format!("must be between {MIN_USERNAME_LENGTH} and {MAX_USERNAME_LENGTH} characters"),
+        );
+    }
+
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        errors.add(
+            field,
+            "can only contain English letters, numbers, underscores and hyphens",
+        );
+    } else if matches!(username.chars().next(), Some(c) if !c.is_ascii_alphanumeric()) {
+        errors.add(field, "must start with a letter or number");
+    }
+
+    if RESERVED_USERNAMES.contains(&username.to_ascii_lowercase().as_str()) {
+        errors.add(field, "is reserved");
+    }
+}
+
+pub fn check_password(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    password: &str,
+    username: &str,
+) {
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        errors.add(
+            field,
+            format!("must be at least {MIN_PASSWORD_LENGTH} characters"),
+        );
+    } else if length > MAX_PASSWORD_LENGTH {
+        errors.add(
+            field,
+            format!("must be at most {MAX_PASSWORD_LENGTH} characters"),
+        );
+    }
+
+    if password.chars().collect::<HashSet<_>>().len() < MIN_DISTINCT_CHARACTERS {
+        errors.add(field, "is too repetitive");
+    }
+
+    let lowercase = password.to_lowercase();
+    if username.chars().count() >= MIN_USERNAME_LENGTH
+        && lowercase.contains(&username.to_lowercase())
+    {
+        errors.add(field, "must not contain your username");
+    }
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+        errors.add(field, "is too common");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(check: impl FnOnce(&mut ValidationErrors)) -> Vec<String> {
+        let mut errors = ValidationErrors::default();
+        check(&mut errors);
+        match serde_json::to_value(&errors).unwrap().get("field") {
+            Some(problems) => serde_json::from_value(problems.clone()).unwrap(),
+            None => vec![],
+        }
+    }
+
+    fn username_problems(username: &str) -> Vec<String> {
+        problems(|errors| check_username(errors, "field", username))
+    }
+
+    fn password_problems(password: &str, username: &str) -> Vec<String> {
+        problems(|errors| check_password(errors, "field", password, username))
+    }
+
+    #[test]
+    fn username_length_counts_characters() {
+        assert!(username_problems("abc").is_empty());
+        assert!(username_problems(&"a".repeat(MAX_USERNAME_LENGTH)).is_empty());
+        assert_eq!(
+            username_problems("ab"),
+            vec!["must be between 3 and 32 characters"]
+        );
+        assert_eq!(
+            username_problems(&"a".repeat(MAX_USERNAME_LENGTH + 1)),
+            vec!["must be between 3 and 32 characters"]
+        );
+        // 2 characters, but more than 3 bytes
+        assert!(
+            username_problems("éé").contains(&"must be between 3 and 32 characters".to_string())
+        );
+    }
+
+    #[test]
+    fn username_charset() {
+        assert!(username_problems("Abc_12-x").is_empty());
+        for username in ["héllo", "two words", "dot.name", "аbc"] {
+            assert_eq!(
+                username_problems(username),
+                vec!["can only contain English letters, numbers, underscores and hyphens"],
+                "{username}"
+            );
+        }
+        for username in ["_abc", "-abc"] {
+            assert_eq!(
+                username_problems(username),
+                vec!["must start with a letter or number"],
+                "{username}"
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_usernames_ignore_case() {
+        assert_eq!(username_problems("admin"), vec!["is reserved"]);
+        assert_eq!(username_problems("Admin"), vec!["is reserved"]);
+        assert!(username_problems("admin1").is_empty());
+    }
+
+    #[test]
+    fn static_profile_routes_are_reserved() {
+        assert_eq!(username_problems("coverage"), vec!["is reserved"]);
+    }
+
+    #[test]
+    fn password_length_counts_characters() {
+        assert!(password_problems("correct horse", "user").is_empty());
+        assert_eq!(
+            password_problems("abcdef1", "user"),
+            vec!["must be at least 8 characters"]
+        );
+        // 4 characters, but 8 bytes
+        assert!(password_problems("éàüö", "user")
+            .contains(&"must be at least 8 characters".to_string()));
+        assert!(password_problems(&"abcd".repeat(MAX_PASSWORD_LENGTH / 4), "user").is_empty());
+        assert_eq!(
+            password_problems(&"abcd".repeat(MAX_PASSWORD_LENGTH / 4 + 1), "user"),
+            vec!["must be at most 128 characters"]
+        );
+    }
+
+    #[test]
+    fn repetitive_passwords() {
+        assert_eq!(
+            password_problems("aaaaaaaa", "user"),
+            vec!["is too repetitive"]
+        );
+        assert_eq!(
+            password_problems("12121212", "user"),
+            vec!["is too repetitive"]
+        );
+        assert_eq!(
+            password_problems("abcabcabc", "user"),
+            vec!["is too repetitive"]
+        );
+        assert!(password_problems("abcdabcd", "user").is_empty());
+    }
+
+    #[test]
+    fn common_passwords_ignore_case() {
+        assert_eq!(
+            password_problems("Password1", "user"),
+            vec!["is too common"]
+        );
+        assert_eq!(password_problems("SUNSHINE", "user"), vec!["is too common"]);
+    }
+
+    #[test]
+    fn password_must_not_contain_username() {
+        assert_eq!(
+            password_problems("my-Alice-pass", "alice"),
+            vec!["must not contain your username"]
+        );
+        // Too short to be worth checking
+        assert!(password_problems("ab-cdefgh", "ab").is_empty());
+    }
+}